@@ -0,0 +1,37 @@
+use freenect_async::{context::FreenectContext, formats::FreenectDepthFormat, FreenectError};
+use lending_stream::LendingStream;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    run().await.unwrap();
+}
+
+async fn run() -> Result<(), FreenectError> {
+    let ctx = FreenectContext::new()?.setup_video();
+
+    let serials = ctx.device_attributes()?;
+    println!("Devices connected: {:?}", serials);
+
+    let devices = serials
+        .iter()
+        .map(|serial| ctx.open_device_by_serial(serial))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut streams = Vec::with_capacity(devices.len());
+    for dev in &devices {
+        let dmode = dev
+            .get_supported_depth_modes()
+            .into_iter()
+            .find(|a| a.format == FreenectDepthFormat::DepthMillimeters.into())
+            .unwrap();
+        streams.push(dev.start_depth_stream(&dmode)?);
+    }
+
+    loop {
+        // polling any stream processes events for all of them
+        for (serial, stream) in serials.iter().zip(streams.iter_mut()) {
+            let frame = stream.next().await.unwrap()?;
+            println!("{}: {}", serial, frame.timestamp);
+        }
+    }
+}
@@ -10,7 +10,7 @@ async fn main() {
 async fn run() -> Result<(), FreenectError> {
     let ctx = FreenectContext::new()?;
 
-    let mut ctx = ctx.setup_all();
+    let ctx = ctx.setup_all();
     ctx.set_log_level(FreenectLogLevel::Spew);
     let num = ctx.list_devices()?;
     println!("Devices connected: {}", num);
@@ -19,7 +19,7 @@ async fn run() -> Result<(), FreenectError> {
     }
     println!("Opening first device.");
 
-    let mut dev = ctx.open_device(0)?;
+    let dev = ctx.open_device(0)?;
 
     dev.set_led(FreenectLedState::Yellow)?;
 
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use freenect_async::{
+    context::FreenectContext, formats::FreenectDepthFormat, motors_led::FreenectLedState,
+    shared::SharedContext, FreenectError,
+};
+use lending_stream::LendingStream;
+
+#[tokio::main]
+async fn main() {
+    run().await.unwrap();
+}
+
+async fn run() -> Result<(), FreenectError> {
+    let ctx = SharedContext::new(FreenectContext::new()?.setup_all());
+    if ctx.with(|ctx| ctx.list_devices())? == 0 {
+        return Ok(());
+    }
+
+    let dev = ctx.open_device(0)?;
+    let dmode = dev.with(|dev| {
+        dev.get_supported_depth_modes()
+            .into_iter()
+            .find(|a| a.format == FreenectDepthFormat::DepthMillimeters.into())
+            .unwrap()
+    });
+
+    let mut stream = dev.start_depth_stream(&dmode)?;
+    let capture = tokio::spawn(async move {
+        for _ in 0..300 {
+            let frame = stream.next().await.unwrap()?;
+            println!("{}", frame.timestamp);
+        }
+        Ok::<_, FreenectError>(())
+    });
+
+    // motor and LED commands can be sent from other tasks while streaming
+    let blink = tokio::spawn(async move {
+        for state in [FreenectLedState::Red, FreenectLedState::Green].repeat(5) {
+            dev.with(|dev| dev.set_led(state))?;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        Ok::<_, FreenectError>(())
+    });
+
+    capture.await.unwrap()?;
+    blink.await.unwrap()?;
+    Ok(())
+}
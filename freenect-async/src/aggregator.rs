@@ -0,0 +1,288 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{AlgorithmError, FreenectError};
+
+// samples used to fit each device's timestamp clock against host time
+const CLOCK_WINDOW: usize = 64;
+// frames kept per device while waiting for the other devices to catch up
+const MAX_PENDING: usize = 8;
+
+/// A frame from one device of the rig, owned so it can wait for its peers.
+#[derive(Debug, Clone)]
+pub struct TimedFrame<T> {
+    pub device: usize,
+    pub timestamp: u32,
+    pub arrival: Instant,
+    /// Arrival time corrected with the device clock estimate.
+    pub time: Instant,
+    pub frame: T,
+}
+
+/// One frame per device, in device order, all within the aggregator's tolerance.
+#[derive(Debug, Clone)]
+pub struct FrameBundle<T> {
+    pub time: Instant,
+    pub frames: Vec<TimedFrame<T>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SkewStats {
+    pub bundles: u64,
+    pub dropped: u64,
+    pub mean_ms: f64,
+    pub std_dev_ms: f64,
+    pub max_abs_ms: f64,
+    m2: f64,
+}
+
+impl SkewStats {
+    fn record(&mut self, skew_ms: f64) {
+        self.bundles += 1;
+        let delta = skew_ms - self.mean_ms;
+        self.mean_ms += delta / self.bundles as f64;
+        self.m2 += delta * (skew_ms - self.mean_ms);
+        self.std_dev_ms = (self.m2 / self.bundles as f64).sqrt();
+        self.max_abs_ms = self.max_abs_ms.max(skew_ms.abs());
+    }
+}
+
+/// Maps a device's `u32` frame timestamps to host time with a least squares fit
+/// over recent frames, which smooths out USB transfer jitter in arrival times.
+#[derive(Debug, Clone, Default)]
+struct DeviceClock {
+    last_timestamp: Option<u32>,
+    wraps: u64,
+    first_tick: Option<u64>,
+    samples: VecDeque<(f64, f64)>,
+}
+
+impl DeviceClock {
+    fn unwrap(&mut self, timestamp: u32) -> u64 {
+        if let Some(last) = self.last_timestamp {
+            if timestamp < last && last - timestamp > u32::MAX / 2 {
+                self.wraps += 1;
+            }
+        }
+        self.last_timestamp = Some(timestamp);
+        (self.wraps << 32) | timestamp as u64
+    }
+
+    /// Returns the estimated host time of the frame, in seconds since `epoch`.
+    fn observe(&mut self, timestamp: u32, arrival_secs: f64) -> f64 {
+        let tick = self.unwrap(timestamp);
+        let first = *self.first_tick.get_or_insert(tick);
+        let x = (tick - first) as f64;
+
+        if self.samples.len() == CLOCK_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((x, arrival_secs));
+
+        let n = self.samples.len() as f64;
+        if n < 2.0 {
+            return arrival_secs;
+        }
+        let (sx, sy) = self
+            .samples
+            .iter()
+            .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+        let (mx, my) = (sx / n, sy / n);
+        let (sxx, sxy) = self.samples.iter().fold((0.0, 0.0), |(sxx, sxy), (x, y)| {
+            (sxx + (x - mx) * (x - mx), sxy + (x - mx) * (y - my))
+        });
+        if sxx <= f64::EPSILON {
+            return arrival_secs;
+        }
+        let slope = sxy / sxx;
+        my + slope * (x - mx)
+    }
+}
+
+/// Groups frames from several devices into time-aligned [`FrameBundle`]s.
+///
+/// Frames are pushed as they come out of each device's stream. A bundle is
+/// emitted once every device has a frame within `tolerance` of the others;
+/// frames that can't be matched are dropped and counted in [`SkewStats`].
+#[derive(Debug)]
+pub struct FrameAggregator<T> {
+    epoch: Instant,
+    tolerance: Duration,
+    clocks: Vec<DeviceClock>,
+    pending: Vec<VecDeque<TimedFrame<T>>>,
+    stats: Vec<SkewStats>,
+}
+
+impl<T> FrameAggregator<T> {
+    pub fn new(devices: usize, tolerance: Duration) -> Self {
+        Self {
+            epoch: Instant::now(),
+            tolerance,
+            clocks: vec![DeviceClock::default(); devices],
+            pending: (0..devices).map(|_| VecDeque::new()).collect(),
+            stats: vec![SkewStats::default(); devices],
+        }
+    }
+
+    pub fn devices(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn push(&mut self, device: usize, timestamp: u32, frame: T) -> Result<(), FreenectError> {
+        self.push_at(device, timestamp, Instant::now(), frame)
+    }
+
+    /// Like [`push`](Self::push), for frames whose host arrival time was recorded earlier.
+    pub fn push_at(
+        &mut self,
+        device: usize,
+        timestamp: u32,
+        arrival: Instant,
+        frame: T,
+    ) -> Result<(), FreenectError> {
+        if device >= self.devices() {
+            return Err(FreenectError::Algorithm(
+                AlgorithmError::AggregatorDeviceOutOfRange(device, self.devices()),
+            ));
+        }
+        let arrival_secs = arrival.saturating_duration_since(self.epoch).as_secs_f64();
+        let secs = self.clocks[device].observe(timestamp, arrival_secs);
+        let time = self.epoch + Duration::from_secs_f64(secs.max(0.0));
+
+        let queue = &mut self.pending[device];
+        if queue.len() == MAX_PENDING {
+            queue.pop_front();
+            self.stats[device].dropped += 1;
+        }
+        queue.push_back(TimedFrame {
+            device,
+            timestamp,
+            arrival,
+            time,
+            frame,
+        });
+        Ok(())
+    }
+
+    pub fn pop_bundle(&mut self) -> Option<FrameBundle<T>> {
+        loop {
+            let newest = self
+                .pending
+                .iter()
+                .map(|queue| queue.front().map(|f| f.time))
+                .collect::<Option<Vec<_>>>()?
+                .into_iter()
+                .max()?;
+
+            // heads too old to be matched with the newest head will never be
+            let mut dropped = false;
+            for (queue, stats) in self.pending.iter_mut().zip(self.stats.iter_mut()) {
+                if let Some(head) = queue.front() {
+                    if newest.duration_since(head.time) > self.tolerance {
+                        queue.pop_front();
+                        stats.dropped += 1;
+                        dropped = true;
+                    }
+                }
+            }
+            if dropped {
+                continue;
+            }
+
+            let frames: Vec<_> = self
+                .pending
+                .iter_mut()
+                .map(|queue| queue.pop_front().unwrap())
+                .collect();
+            let mean_secs = frames
+                .iter()
+                .map(|f| f.time.duration_since(self.epoch).as_secs_f64())
+                .sum::<f64>()
+                / frames.len() as f64;
+            for (frame, stats) in frames.iter().zip(self.stats.iter_mut()) {
+                let secs = frame.time.duration_since(self.epoch).as_secs_f64();
+                stats.record((secs - mean_secs) * 1000.0);
+            }
+
+            return Some(FrameBundle {
+                time: self.epoch + Duration::from_secs_f64(mean_secs),
+                frames,
+            });
+        }
+    }
+
+    pub fn skew_stats(&self) -> &[SkewStats] {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: Duration = Duration::from_millis(10);
+
+    // pushes a frame whose timestamp ticks once per millisecond since the epoch
+    fn push(aggregator: &mut FrameAggregator<u32>, device: usize, ms: u64) {
+        let arrival = aggregator.epoch + Duration::from_millis(ms);
+        aggregator
+            .push_at(device, ms as u32, arrival, ms as u32)
+            .unwrap();
+    }
+
+    #[test]
+    fn pairs_frames_within_tolerance() {
+        let mut aggregator = FrameAggregator::new(2, TOLERANCE);
+        for ms in [0, 33, 66] {
+            push(&mut aggregator, 0, ms);
+            assert!(aggregator.pop_bundle().is_none());
+            push(&mut aggregator, 1, ms + 5);
+            let bundle = aggregator.pop_bundle().unwrap();
+            let frames: Vec<_> = bundle.frames.iter().map(|f| f.frame).collect();
+            assert_eq!(frames, [ms as u32, ms as u32 + 5]);
+        }
+        let stats = aggregator.skew_stats();
+        assert_eq!(stats[0].bundles, 3);
+        assert_eq!(stats[1].dropped, 0);
+        assert!((stats[0].mean_ms + 2.5).abs() < 0.1);
+        assert!((stats[1].max_abs_ms - 2.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn drops_frames_without_a_match() {
+        let mut aggregator = FrameAggregator::new(2, TOLERANCE);
+        push(&mut aggregator, 0, 0);
+        push(&mut aggregator, 1, 50);
+        assert!(aggregator.pop_bundle().is_none());
+        assert_eq!(aggregator.skew_stats()[0].dropped, 1);
+
+        push(&mut aggregator, 0, 52);
+        let bundle = aggregator.pop_bundle().unwrap();
+        assert_eq!(bundle.frames[0].frame, 52);
+        assert_eq!(bundle.frames[1].frame, 50);
+    }
+
+    #[test]
+    fn evicts_the_oldest_pending_frame() {
+        let mut aggregator = FrameAggregator::new(2, TOLERANCE);
+        for i in 0..=MAX_PENDING as u64 {
+            push(&mut aggregator, 0, i * 33);
+        }
+        assert_eq!(aggregator.pending[0].len(), MAX_PENDING);
+        assert_eq!(aggregator.pending[0].front().unwrap().frame, 33);
+        assert_eq!(aggregator.skew_stats()[0].dropped, 1);
+    }
+
+    #[test]
+    fn rejects_unknown_devices() {
+        let mut aggregator = FrameAggregator::new(2, TOLERANCE);
+        assert!(matches!(
+            aggregator.push(2, 0, 0),
+            Err(FreenectError::Algorithm(
+                AlgorithmError::AggregatorDeviceOutOfRange(2, 2)
+            ))
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+use std::task::Poll;
+
+use lending_stream::LendingStream;
+
+use crate::{
+    device::FreenectDevice,
+    formats::{FreenectFormat, FreenectVideoFormat, FreenectVideoMode},
+    stats::StreamStats,
+    stream::VideoStream,
+    video::FreenectVideo,
+    FreenectError,
+};
+
+// frames dropped after each switch while the sensor adjusts to the new format
+const DEFAULT_SETTLE_FRAMES: u32 = 2;
+
+/// A video frame tagged with the format it was captured in.
+#[derive(Debug)]
+pub struct TaggedFrame<'c> {
+    pub format: FreenectVideoFormat,
+    pub mode: FreenectVideoMode,
+    pub timestamp: u32,
+    pub data: &'c [u8],
+}
+
+/// Alternates a video stream between an RGB and an IR mode every
+/// `frames_per_mode` frames, for calibrating the two cameras against each other.
+///
+/// If switching modes fails the error is yielded and the stream carries on in
+/// the current mode, trying again after another `frames_per_mode` frames.
+#[derive(Debug)]
+pub struct AlternatingStream<'a, 'b, D: FreenectVideo> {
+    stream: VideoStream<'a, 'b, D>,
+    modes: [(FreenectVideoFormat, FreenectVideoMode); 2],
+    schedule: Schedule,
+}
+
+impl<'a, 'b, D: FreenectVideo> AlternatingStream<'a, 'b, D> {
+    pub(crate) fn new(
+        device: &'b FreenectDevice<'a, D>,
+        rgb: &FreenectVideoMode,
+        ir: &FreenectVideoMode,
+        frames_per_mode: u32,
+    ) -> Result<Self, FreenectError> {
+        let rgb_format = match rgb.format {
+            FreenectFormat::Video(f @ FreenectVideoFormat::Rgb) => f,
+            _ => return Err(FreenectError::BadVideoFormat),
+        };
+        let ir_format = match ir.format {
+            FreenectFormat::Video(
+                f @ (FreenectVideoFormat::Ir8Bit | FreenectVideoFormat::Ir10Bit),
+            ) => f,
+            _ => return Err(FreenectError::BadVideoFormat),
+        };
+
+        Ok(Self {
+            stream: device.start_video_stream(rgb)?,
+            modes: [(rgb_format, *rgb), (ir_format, *ir)],
+            schedule: Schedule::new(frames_per_mode, DEFAULT_SETTLE_FRAMES),
+        })
+    }
+
+    /// Number of frames discarded after starting and after each switch.
+    pub fn with_settle_frames(mut self, frames: u32) -> Self {
+        self.schedule.settling = frames;
+        self.schedule.settle_frames = frames;
+        self
+    }
+
+    pub fn current_format(&self) -> FreenectVideoFormat {
+        self.modes[self.schedule.current].0
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.stream.stats()
+    }
+}
+
+impl<'a, 'b, D: FreenectVideo> LendingStream for AlternatingStream<'a, 'b, D> {
+    type Item<'c> = Result<TaggedFrame<'c>, FreenectError> where Self: 'c;
+
+    fn poll_next(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item<'_>>> {
+        if self.schedule.is_due() {
+            let next = self.schedule.next();
+            if let Err(e) = self.stream.set_mode(&self.modes[next].1) {
+                self.schedule.switch_failed();
+                return Poll::Ready(Some(Err(e)));
+            }
+            self.schedule.switched();
+        }
+
+        let (format, mode) = self.modes[self.schedule.current];
+        match self.stream.poll_next(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if !self.schedule.frame() {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(Some(Ok(TaggedFrame {
+                    format,
+                    mode,
+                    timestamp: frame.timestamp,
+                    data: frame.data,
+                })))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Which mode frames come from and which of them are kept, apart from the
+/// stream itself.
+#[derive(Debug, Clone, Copy)]
+struct Schedule {
+    current: usize,
+    frames_per_mode: u32,
+    settle_frames: u32,
+    yielded: u32,
+    settling: u32,
+}
+
+impl Schedule {
+    fn new(frames_per_mode: u32, settle_frames: u32) -> Self {
+        Self {
+            current: 0,
+            frames_per_mode: frames_per_mode.max(1),
+            settle_frames,
+            yielded: 0,
+            settling: settle_frames,
+        }
+    }
+
+    /// Whether the stream should switch before taking the next frame.
+    fn is_due(&self) -> bool {
+        self.yielded >= self.frames_per_mode
+    }
+
+    fn next(&self) -> usize {
+        1 - self.current
+    }
+
+    fn switched(&mut self) {
+        self.current = self.next();
+        self.yielded = 0;
+        self.settling = self.settle_frames;
+    }
+
+    fn switch_failed(&mut self) {
+        self.yielded = 0;
+    }
+
+    /// Counts a received frame, returns whether it is kept.
+    fn frame(&mut self) -> bool {
+        if self.settling > 0 {
+            self.settling -= 1;
+            return false;
+        }
+        self.yielded += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // feeds `frames` frames through the schedule, returning the mode of each
+    // kept frame, or `None` for discarded ones
+    fn run(schedule: &mut Schedule, frames: usize, switch_ok: bool) -> Vec<Option<usize>> {
+        (0..frames)
+            .map(|_| {
+                if schedule.is_due() {
+                    if switch_ok {
+                        schedule.switched();
+                    } else {
+                        schedule.switch_failed();
+                    }
+                }
+                schedule.frame().then_some(schedule.current)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn alternates_after_settling() {
+        let mut schedule = Schedule::new(3, 2);
+        let modes = run(&mut schedule, 12, true);
+        #[rustfmt::skip]
+        let expected = [
+            None, None, Some(0), Some(0), Some(0),
+            None, None, Some(1), Some(1), Some(1),
+            None, None,
+        ];
+        assert_eq!(modes, expected);
+        assert_eq!(schedule.current, 0);
+    }
+
+    #[test]
+    fn keeps_every_frame_without_settling() {
+        let mut schedule = Schedule::new(2, 0);
+        let modes = run(&mut schedule, 6, true);
+        let expected = [0, 0, 1, 1, 0, 0].map(Some);
+        assert_eq!(modes, expected);
+    }
+
+    #[test]
+    fn stays_in_the_current_mode_when_switching_fails() {
+        let mut schedule = Schedule::new(2, 1);
+        let modes = run(&mut schedule, 4, false);
+        assert_eq!(modes, [None, Some(0), Some(0), Some(0)]);
+        assert!(!schedule.is_due());
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{filters::DepthImage, FreenectError};
+
+/// Per pixel depth background, learned from the first frames it sees and then
+/// slowly updated with every frame afterwards.
+///
+/// A pixel is foreground when it is closer than the background by more than
+/// `threshold_sigma` standard deviations and at least `min_diff_mm`. Pixels that
+/// never had a reading while learning (e.g. beyond the sensor range) are taken
+/// as background, learned from their first reading on. Foreground that stays
+/// put is absorbed into the background at `absorption_rate`.
+#[derive(Debug, Clone)]
+pub struct BackgroundModel {
+    width: usize,
+    height: usize,
+    mean: Vec<f32>,
+    variance: Vec<f32>,
+    samples: Vec<u32>,
+    frames: u32,
+    learning_frames: u32,
+    /// Weight of each new frame when updating the background after learning.
+    pub learning_rate: f32,
+    /// Weight of each new frame in pixels found to be foreground, so objects
+    /// left in the scene eventually become background.
+    pub absorption_rate: f32,
+    pub threshold_sigma: f32,
+    pub min_diff_mm: u16,
+}
+
+impl BackgroundModel {
+    pub fn new(width: usize, height: usize, learning_frames: u32) -> Self {
+        let len = width * height;
+        Self {
+            width,
+            height,
+            mean: vec![0.0; len],
+            variance: vec![0.0; len],
+            samples: vec![0; len],
+            frames: 0,
+            learning_frames: learning_frames.max(1),
+            learning_rate: 0.01,
+            absorption_rate: 0.001,
+            threshold_sigma: 3.0,
+            min_diff_mm: 50,
+        }
+    }
+
+    pub fn with_learning_rate(mut self, rate: f32) -> Self {
+        self.learning_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_threshold(mut self, sigma: f32, min_diff_mm: u16) -> Self {
+        self.threshold_sigma = sigma;
+        self.min_diff_mm = min_diff_mm;
+        self
+    }
+
+    pub fn is_learned(&self) -> bool {
+        self.frames >= self.learning_frames
+    }
+
+    /// Starts learning the background again from scratch.
+    pub fn reset(&mut self) {
+        self.mean.fill(0.0);
+        self.variance.fill(0.0);
+        self.samples.fill(0);
+        self.frames = 0;
+    }
+
+    /// Background depth in millimetres, 0 where it is unknown.
+    pub fn background(&self) -> DepthImage {
+        DepthImage {
+            width: self.width,
+            height: self.height,
+            data: self.mean.iter().map(|&m| m.round() as u16).collect(),
+        }
+    }
+
+    /// Feeds a frame into the model. Returns `None` while the background is still
+    /// being learned and the foreground of the frame afterwards.
+    pub fn update(&mut self, image: &DepthImage) -> Result<Option<ForegroundMask>, FreenectError> {
+        if image.width != self.width || image.height != self.height {
+            return Err(FreenectError::BadVideoFormat);
+        }
+
+        if !self.is_learned() {
+            self.learn(image);
+            return Ok(None);
+        }
+
+        let mut mask = ForegroundMask {
+            width: self.width,
+            height: self.height,
+            data: vec![false; image.data.len()],
+        };
+        for (i, &d) in image.data.iter().enumerate() {
+            if d == 0 {
+                continue;
+            }
+            let value = d as f32;
+            if self.samples[i] == 0 {
+                self.samples[i] = 1;
+                self.mean[i] = value;
+                continue;
+            }
+            let threshold =
+                (self.threshold_sigma * self.variance[i].sqrt()).max(self.min_diff_mm as f32);
+            let foreground = self.mean[i] - value > threshold;
+            mask.data[i] = foreground;
+
+            // exponentially weighted mean and variance
+            let rate = if foreground {
+                self.absorption_rate
+            } else {
+                self.learning_rate
+            };
+            let diff = value - self.mean[i];
+            let increment = rate * diff;
+            self.mean[i] += increment;
+            self.variance[i] = (1.0 - rate) * (self.variance[i] + diff * increment);
+        }
+        Ok(Some(mask))
+    }
+
+    fn learn(&mut self, image: &DepthImage) {
+        for (i, &d) in image.data.iter().enumerate() {
+            if d == 0 {
+                continue;
+            }
+            // Welford's running mean and variance
+            self.samples[i] += 1;
+            let n = self.samples[i] as f32;
+            let value = d as f32;
+            let diff = value - self.mean[i];
+            self.mean[i] += diff / n;
+            let m2 = self.variance[i] * (n - 1.0) + diff * (value - self.mean[i]);
+            self.variance[i] = m2 / n;
+        }
+        self.frames += 1;
+    }
+}
+
+/// Pixels belonging to the foreground of a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForegroundMask {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<bool>,
+}
+
+impl ForegroundMask {
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.data[y * self.width + x]
+    }
+
+    pub fn count(&self) -> usize {
+        self.data.iter().filter(|&&f| f).count()
+    }
+
+    /// Connected regions of foreground with at least `min_pixels` pixels,
+    /// largest first. Pixels touching diagonally are connected.
+    pub fn blobs(&self, min_pixels: usize) -> Vec<Blob> {
+        let (width, height) = (self.width, self.height);
+        let mut visited = vec![false; self.data.len()];
+        let mut stack = Vec::new();
+        let mut blobs = Vec::new();
+        for start in 0..self.data.len() {
+            if visited[start] || !self.data[start] {
+                continue;
+            }
+            visited[start] = true;
+            stack.push(start);
+
+            let mut pixels = 0;
+            let (mut min_x, mut min_y) = (usize::MAX, usize::MAX);
+            let (mut max_x, mut max_y) = (0, 0);
+            let (mut sum_x, mut sum_y) = (0.0, 0.0);
+            while let Some(i) = stack.pop() {
+                let (x, y) = (i % width, i / width);
+                pixels += 1;
+                sum_x += x as f64;
+                sum_y += y as f64;
+                (min_x, max_x) = (min_x.min(x), max_x.max(x));
+                (min_y, max_y) = (min_y.min(y), max_y.max(y));
+
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        let n = ny * width + nx;
+                        if !visited[n] && self.data[n] {
+                            visited[n] = true;
+                            stack.push(n);
+                        }
+                    }
+                }
+            }
+
+            if pixels >= min_pixels {
+                blobs.push(Blob {
+                    pixels,
+                    centroid: [
+                        (sum_x / pixels as f64) as f32,
+                        (sum_y / pixels as f64) as f32,
+                    ],
+                    bounding_box: BoundingBox {
+                        x: min_x,
+                        y: min_y,
+                        width: max_x - min_x + 1,
+                        height: max_y - min_y + 1,
+                    },
+                });
+            }
+        }
+        blobs.sort_by_key(|blob| std::cmp::Reverse(blob.pixels));
+        blobs
+    }
+}
+
+/// A connected region of foreground.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blob {
+    pub pixels: usize,
+    /// Mean pixel position, as `[x, y]`.
+    pub centroid: [f32; 2],
+    pub bounding_box: BoundingBox,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 20;
+    const HEIGHT: usize = 10;
+
+    // a noisy wall at 2 m, with a person and a raised hand in front of it
+    fn frame(seed: &mut u32, person: bool) -> DepthImage {
+        let data = (0..WIDTH * HEIGHT)
+            .map(|i| {
+                *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let (x, y) = (i % WIDTH, i / WIDTH);
+                match (x, y) {
+                    (3..=5, 2..=7) if person => 1000,
+                    (12..=13, 1..=2) if person => 900,
+                    _ => 2000 + (*seed >> 16) as u16 % 10,
+                }
+            })
+            .collect();
+        DepthImage::new(WIDTH, HEIGHT, data).unwrap()
+    }
+
+    fn learned(seed: &mut u32) -> BackgroundModel {
+        let mut model = BackgroundModel::new(WIDTH, HEIGHT, 5);
+        for _ in 0..5 {
+            assert!(model.update(&frame(seed, false)).unwrap().is_none());
+        }
+        assert!(model.is_learned());
+        model
+    }
+
+    #[test]
+    fn detects_foreground_after_learning() {
+        let mut seed = 7;
+        let mut model = learned(&mut seed);
+        let mask = model.update(&frame(&mut seed, false)).unwrap().unwrap();
+        assert_eq!(mask.count(), 0);
+
+        let mask = model.update(&frame(&mut seed, true)).unwrap().unwrap();
+        assert_eq!(mask.count(), 22);
+        let blobs = mask.blobs(2);
+        assert_eq!(blobs.len(), 2);
+        assert_eq!(blobs[0].pixels, 18);
+        assert_eq!(blobs[0].centroid, [4.0, 4.5]);
+        assert_eq!(
+            blobs[0].bounding_box,
+            BoundingBox {
+                x: 3,
+                y: 2,
+                width: 3,
+                height: 6
+            }
+        );
+        assert_eq!(mask.blobs(5).len(), 1);
+    }
+
+    #[test]
+    fn unmodelled_pixels_are_learned_later() {
+        let mut model = BackgroundModel::new(2, 1, 3);
+        for _ in 0..3 {
+            model
+                .update(&DepthImage::new(2, 1, vec![2000, 0]).unwrap())
+                .unwrap();
+        }
+        let mask = model
+            .update(&DepthImage::new(2, 1, vec![2000, 1500]).unwrap())
+            .unwrap();
+        assert_eq!(mask.unwrap().count(), 0);
+        assert_eq!(model.background().data, [2000, 1500]);
+
+        let mask = model
+            .update(&DepthImage::new(2, 1, vec![2000, 1000]).unwrap())
+            .unwrap();
+        assert!(mask.unwrap().get(1, 0));
+    }
+
+    #[test]
+    fn absorbs_static_foreground() {
+        let mut model = BackgroundModel::new(1, 1, 3);
+        model.absorption_rate = 0.05;
+        for _ in 0..3 {
+            model
+                .update(&DepthImage::new(1, 1, vec![2000]).unwrap())
+                .unwrap();
+        }
+        let object = DepthImage::new(1, 1, vec![1000]).unwrap();
+        let first = model.update(&object).unwrap().unwrap();
+        assert_eq!(first.count(), 1);
+        let absorbed = (0..200).any(|_| model.update(&object).unwrap().unwrap().count() == 0);
+        assert!(absorbed);
+        for _ in 0..500 {
+            model.update(&object).unwrap();
+        }
+        assert!(model.background().data[0] < 1050);
+    }
+}
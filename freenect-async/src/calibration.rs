@@ -0,0 +1,1250 @@
+use std::collections::HashMap;
+
+use crate::{
+    formats::{FreenectFormat, FreenectVideoFormat, FreenectVideoMode},
+    AlgorithmError, FreenectError,
+};
+
+// Zhang's closed form solution needs at least three views of the board
+const MIN_VIEWS: usize = 3;
+const MAX_ITERATIONS: usize = 100;
+// smoothing applied before looking for saddle points
+const DETECT_SIGMA: f32 = 2.0;
+// radius of the circle sampled around a candidate to tell X corners from L corners
+const RING_RADIUS: f32 = 4.0;
+const RING_SAMPLES: usize = 16;
+// seeds tried when growing the corner grid, nearest to the candidates' centroid first
+const MAX_SEEDS: usize = 8;
+
+/// A checkerboard target, described by its number of inner corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkerboard {
+    pub cols: usize,
+    pub rows: usize,
+    /// Side of a square, in the unit the extrinsics should be expressed in.
+    pub square_size: f64,
+}
+
+impl Checkerboard {
+    pub fn new(cols: usize, rows: usize, square_size: f64) -> Self {
+        Self {
+            cols,
+            rows,
+            square_size,
+        }
+    }
+
+    /// Board coordinates of the inner corners, row by row.
+    pub fn object_points(&self) -> Vec<[f64; 2]> {
+        (0..self.rows)
+            .flat_map(|r| (0..self.cols).map(move |c| [c as f64, r as f64]))
+            .map(|[x, y]| [x * self.square_size, y * self.square_size])
+            .collect()
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Intrinsics {
+    pub width: u16,
+    pub height: u16,
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+}
+
+impl Intrinsics {
+    pub fn camera_matrix(&self) -> [[f64; 3]; 3] {
+        [
+            [self.fx, 0.0, self.cx],
+            [0.0, self.fy, self.cy],
+            [0.0, 0.0, 1.0],
+        ]
+    }
+}
+
+/// Brown-Conrady lens distortion, with the coefficients in OpenCV's order.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Distortion {
+    pub k1: f64,
+    pub k2: f64,
+    pub p1: f64,
+    pub p2: f64,
+    pub k3: f64,
+}
+
+impl Distortion {
+    /// Applies the distortion to a point on the normalized image plane.
+    pub fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        let xd = x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x);
+        let yd = y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y;
+        (xd, yd)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub intrinsics: Intrinsics,
+    pub distortion: Distortion,
+    /// Root mean square reprojection error over every corner, in pixels.
+    pub rms_error: f64,
+    pub views: usize,
+}
+
+/// Collects checkerboard views from RGB or IR frames and estimates the camera
+/// intrinsics and distortion from them with Zhang's method.
+#[derive(Debug, Clone)]
+pub struct Calibrator {
+    board: Checkerboard,
+    width: u16,
+    height: u16,
+    views: Vec<Vec<[f64; 2]>>,
+}
+
+impl Calibrator {
+    pub fn new(board: Checkerboard, width: u16, height: u16) -> Self {
+        Self {
+            board,
+            width,
+            height,
+            views: Vec::new(),
+        }
+    }
+
+    pub fn board(&self) -> &Checkerboard {
+        &self.board
+    }
+
+    pub fn views(&self) -> usize {
+        self.views.len()
+    }
+
+    /// Looks for the board in a frame and keeps its corners, returns whether it was found.
+    pub fn add_frame(
+        &mut self,
+        mode: &FreenectVideoMode,
+        data: &[u8],
+    ) -> Result<bool, FreenectError> {
+        if mode.width != self.width || mode.height != self.height {
+            return Err(FreenectError::BadVideoFormat);
+        }
+        let gray = to_gray(mode, data)?;
+        match find_corners(
+            &gray,
+            self.width as usize,
+            self.height as usize,
+            &self.board,
+        ) {
+            Some(corners) => {
+                self.views.push(corners);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Adds corners found by other means, in the order of [`Checkerboard::object_points`].
+    pub fn add_corners(&mut self, corners: Vec<[f64; 2]>) -> Result<(), FreenectError> {
+        let expected = self.board.cols * self.board.rows;
+        if corners.len() != expected {
+            return Err(FreenectError::Algorithm(
+                AlgorithmError::InvalidCornerCount {
+                    expected,
+                    got: corners.len(),
+                },
+            ));
+        }
+        self.views.push(corners);
+        Ok(())
+    }
+
+    pub fn calibrate(&self) -> Result<Calibration, FreenectError> {
+        if self.views.len() < MIN_VIEWS {
+            return Err(FreenectError::Algorithm(
+                AlgorithmError::NotEnoughCalibrationViews(self.views.len()),
+            ));
+        }
+        let object = self.board.object_points();
+
+        let homographies = self
+            .views
+            .iter()
+            .map(|image| homography(&object, image))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(AlgorithmError::CalibrationFailed)?;
+        let k = closed_form_intrinsics(&homographies).ok_or(AlgorithmError::CalibrationFailed)?;
+        let poses = homographies
+            .iter()
+            .map(|h| pose_from_homography(&k, h))
+            .collect();
+
+        let mut params = Params {
+            camera: [k[0], k[1], k[2], k[3], 0.0, 0.0, 0.0, 0.0, 0.0],
+            poses,
+        };
+        let sq_error = refine(&mut params, &object, &self.views);
+        if !sq_error.is_finite() || params.camera.iter().any(|p| !p.is_finite()) {
+            return Err(FreenectError::Algorithm(AlgorithmError::CalibrationFailed));
+        }
+
+        let [fx, fy, cx, cy, k1, k2, p1, p2, k3] = params.camera;
+        Ok(Calibration {
+            intrinsics: Intrinsics {
+                width: self.width,
+                height: self.height,
+                fx,
+                fy,
+                cx,
+                cy,
+            },
+            distortion: Distortion { k1, k2, p1, p2, k3 },
+            rms_error: (sq_error / (object.len() * self.views.len()) as f64).sqrt(),
+            views: self.views.len(),
+        })
+    }
+}
+
+/// Converts an RGB, 8 bit IR or 10 bit IR frame to 8 bit grayscale.
+pub fn to_gray(mode: &FreenectVideoMode, data: &[u8]) -> Result<Vec<u8>, FreenectError> {
+    let pixels = mode.width as usize * mode.height as usize;
+    let gray: Vec<u8> = match mode.format {
+        FreenectFormat::Video(FreenectVideoFormat::Rgb) => data
+            .chunks_exact(3)
+            .map(|p| ((77 * p[0] as u32 + 150 * p[1] as u32 + 29 * p[2] as u32) >> 8) as u8)
+            .collect(),
+        FreenectFormat::Video(FreenectVideoFormat::Ir8Bit) => data.to_vec(),
+        FreenectFormat::Video(FreenectVideoFormat::Ir10Bit) => data
+            .chunks_exact(2)
+            .map(|p| (u16::from_ne_bytes([p[0], p[1]]) >> 2).min(255) as u8)
+            .collect(),
+        _ => return Err(FreenectError::BadVideoFormat),
+    };
+    if gray.len() < pixels {
+        return Err(FreenectError::BadVideoFormat);
+    }
+    Ok(gray)
+}
+
+/// Finds the inner corners of `board` in a grayscale image, ordered like
+/// [`Checkerboard::object_points`] and refined to subpixel accuracy.
+pub fn find_corners(
+    gray: &[u8],
+    width: usize,
+    height: usize,
+    board: &Checkerboard,
+) -> Option<Vec<[f64; 2]>> {
+    let expected = board.cols * board.rows;
+    if expected < 4 || gray.len() < width * height {
+        return None;
+    }
+    let image: Vec<f32> = gray[..width * height].iter().map(|&p| p as f32).collect();
+    let smooth = blur(&image, width, height, DETECT_SIGMA);
+    let candidates = saddle_points(&smooth, width, height);
+    if candidates.len() < expected {
+        return None;
+    }
+
+    let grid = grow_grid(&candidates, board)?;
+    let spacing = grid_spacing(&grid, board);
+    let fine = blur(&image, width, height, 1.0);
+    let half = ((spacing / 4.0).round() as isize).clamp(2, 6);
+    Some(
+        grid.into_iter()
+            .map(|p| refine_corner(&fine, width, height, p, half))
+            .collect(),
+    )
+}
+
+fn blur(image: &[f32], width: usize, height: usize, sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let norm: f32 = kernel.iter().sum();
+
+    let mut tmp = vec![0.0; image.len()];
+    let mut out = vec![0.0; image.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (k, w) in kernel.iter().enumerate() {
+                let sx = (x as isize + k as isize - radius).clamp(0, width as isize - 1) as usize;
+                acc += w * image[y * width + sx];
+            }
+            tmp[y * width + x] = acc / norm;
+        }
+    }
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (k, w) in kernel.iter().enumerate() {
+                let sy = (y as isize + k as isize - radius).clamp(0, height as isize - 1) as usize;
+                acc += w * tmp[sy * width + x];
+            }
+            out[y * width + x] = acc / norm;
+        }
+    }
+    out
+}
+
+fn sample(image: &[f32], width: usize, height: usize, x: f32, y: f32) -> f32 {
+    let x = x.clamp(0.0, (width - 1) as f32);
+    let y = y.clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let top = image[y0 * width + x0] * (1.0 - fx) + image[y0 * width + x1] * fx;
+    let bottom = image[y1 * width + x0] * (1.0 - fx) + image[y1 * width + x1] * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Local maxima of the negated Hessian determinant that look like the meeting
+/// point of four squares.
+fn saddle_points(image: &[f32], width: usize, height: usize) -> Vec<[f64; 2]> {
+    let margin = RING_RADIUS.ceil() as usize + 1;
+    if width <= 2 * margin || height <= 2 * margin {
+        return Vec::new();
+    }
+    let at = |x: usize, y: usize| image[y * width + x];
+    let mut response = vec![0.0f32; image.len()];
+    for y in margin..height - margin {
+        for x in margin..width - margin {
+            let ixx = at(x + 1, y) + at(x - 1, y) - 2.0 * at(x, y);
+            let iyy = at(x, y + 1) + at(x, y - 1) - 2.0 * at(x, y);
+            let ixy =
+                (at(x + 1, y + 1) + at(x - 1, y - 1) - at(x + 1, y - 1) - at(x - 1, y + 1)) / 4.0;
+            response[y * width + x] = (ixy * ixy - ixx * iyy).max(0.0);
+        }
+    }
+    let max = response.iter().cloned().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return Vec::new();
+    }
+    let threshold = max * 0.05;
+
+    let mut points = Vec::new();
+    for y in margin..height - margin {
+        for x in margin..width - margin {
+            let r = response[y * width + x];
+            if r < threshold {
+                continue;
+            }
+            let is_max = (y - 2..=y + 2).all(|ny| {
+                (x - 2..=x + 2).all(|nx| {
+                    let n = response[ny * width + nx];
+                    n < r || (n == r && (ny, nx) >= (y, x))
+                })
+            });
+            if is_max && looks_like_x_corner(image, width, height, x as f32, y as f32) {
+                points.push([x as f64, y as f64]);
+            }
+        }
+    }
+    points
+}
+
+/// Around an X corner the intensity alternates four times and is symmetric
+/// about the centre, unlike on the outer corners of the board.
+fn looks_like_x_corner(image: &[f32], width: usize, height: usize, x: f32, y: f32) -> bool {
+    let ring: Vec<f32> = (0..RING_SAMPLES)
+        .map(|i| {
+            let angle = i as f32 * std::f32::consts::TAU / RING_SAMPLES as f32;
+            sample(
+                image,
+                width,
+                height,
+                x + RING_RADIUS * angle.cos(),
+                y + RING_RADIUS * angle.sin(),
+            )
+        })
+        .collect();
+    let (min, max) = ring
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    let contrast = max - min;
+    if contrast < 10.0 {
+        return false;
+    }
+    let mean = (min + max) / 2.0;
+    let crossings = (0..RING_SAMPLES)
+        .filter(|&i| (ring[i] > mean) != (ring[(i + 1) % RING_SAMPLES] > mean))
+        .count();
+    let half = RING_SAMPLES / 2;
+    let asymmetry = (0..half)
+        .map(|i| (ring[i] - ring[i + half]).abs())
+        .sum::<f32>()
+        / half as f32;
+    crossings == 4 && asymmetry < contrast * 0.3
+}
+
+fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn norm(a: [f64; 2]) -> f64 {
+    a[0].hypot(a[1])
+}
+
+/// Orders candidates into the board's grid, returning the corners in object point order.
+fn grow_grid(candidates: &[[f64; 2]], board: &Checkerboard) -> Option<Vec<[f64; 2]>> {
+    let n = candidates.len() as f64;
+    let centroid = candidates
+        .iter()
+        .fold([0.0, 0.0], |acc, p| [acc[0] + p[0] / n, acc[1] + p[1] / n]);
+    let mut seeds: Vec<usize> = (0..candidates.len()).collect();
+    seeds.sort_by(|&a, &b| {
+        norm(sub(candidates[a], centroid)).total_cmp(&norm(sub(candidates[b], centroid)))
+    });
+
+    seeds
+        .into_iter()
+        .take(MAX_SEEDS)
+        .find_map(|seed| grow_from(candidates, seed, board))
+}
+
+// candidate at a grid position, with the local steps to its neighbours along i and j
+type GridCell = (usize, [f64; 2], [f64; 2]);
+
+fn grow_from(candidates: &[[f64; 2]], seed: usize, board: &Checkerboard) -> Option<Vec<[f64; 2]>> {
+    let origin = candidates[seed];
+    let mut nearest: Vec<usize> = (0..candidates.len()).filter(|&i| i != seed).collect();
+    nearest.sort_by(|&a, &b| {
+        norm(sub(candidates[a], origin)).total_cmp(&norm(sub(candidates[b], origin)))
+    });
+    let u = sub(candidates[*nearest.first()?], origin);
+    let v = nearest.iter().take(8).skip(1).find_map(|&i| {
+        let d = sub(candidates[i], origin);
+        let cos = (d[0] * u[0] + d[1] * u[1]) / (norm(d) * norm(u));
+        let ratio = norm(d) / norm(u);
+        (cos.abs() < 0.5 && (0.5..2.0).contains(&ratio)).then_some(d)
+    })?;
+
+    let mut cells: HashMap<(i32, i32), GridCell> = HashMap::new();
+    let mut used = vec![false; candidates.len()];
+    let mut queue = vec![(0, 0)];
+    cells.insert((0, 0), (seed, u, v));
+    used[seed] = true;
+
+    while let Some((i, j)) = queue.pop() {
+        let (idx, u, v) = cells[&(i, j)];
+        let p = candidates[idx];
+        for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let cell = (i + di, j + dj);
+            if cells.contains_key(&cell) {
+                continue;
+            }
+            let (di, dj) = (di as f64, dj as f64);
+            let predicted = [p[0] + di * u[0] + dj * v[0], p[1] + di * u[1] + dj * v[1]];
+            let tolerance = 0.35 * norm(u).min(norm(v));
+            let found = (0..candidates.len())
+                .filter(|&c| !used[c])
+                .map(|c| (c, norm(sub(candidates[c], predicted))))
+                .filter(|&(_, d)| d < tolerance)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((c, _)) = found {
+                let step = sub(candidates[c], p);
+                let nu = if di != 0.0 {
+                    [step[0] * di, step[1] * di]
+                } else {
+                    u
+                };
+                let nv = if dj != 0.0 {
+                    [step[0] * dj, step[1] * dj]
+                } else {
+                    v
+                };
+                used[c] = true;
+                cells.insert(cell, (c, nu, nv));
+                queue.push(cell);
+            }
+        }
+        if cells.len() > board.cols * board.rows {
+            return None;
+        }
+    }
+    if cells.len() != board.cols * board.rows {
+        return None;
+    }
+
+    let min_i = cells.keys().map(|k| k.0).min()?;
+    let min_j = cells.keys().map(|k| k.1).min()?;
+    let span_i = (cells.keys().map(|k| k.0).max()? - min_i + 1) as usize;
+    let span_j = (cells.keys().map(|k| k.1).max()? - min_j + 1) as usize;
+    let at = |c: usize, r: usize, transposed: bool| {
+        let (i, j) = if transposed { (r, c) } else { (c, r) };
+        cells
+            .get(&(min_i + i as i32, min_j + j as i32))
+            .map(|e| candidates[e.0])
+    };
+    let transposed = match (span_i, span_j) {
+        (i, j) if i == board.cols && j == board.rows => false,
+        (i, j) if i == board.rows && j == board.cols => true,
+        _ => return None,
+    };
+
+    let mut corners = Vec::with_capacity(board.cols * board.rows);
+    for r in 0..board.rows {
+        for c in 0..board.cols {
+            corners.push(at(c, r, transposed)?);
+        }
+    }
+    orient(&mut corners, board);
+    Some(corners)
+}
+
+/// Mirrors the ordering if needed so that it is seen from the front of the board,
+/// then picks the rotation starting closest to the top left of the image.
+fn orient(corners: &mut [[f64; 2]], board: &Checkerboard) {
+    let (cols, rows) = (board.cols, board.rows);
+    let along_cols = sub(corners[cols - 1], corners[0]);
+    let along_rows = sub(corners[(rows - 1) * cols], corners[0]);
+    if along_cols[0] * along_rows[1] - along_cols[1] * along_rows[0] < 0.0 {
+        for row in corners.chunks_mut(cols) {
+            row.reverse();
+        }
+    }
+    let first = corners[0];
+    let last = corners[corners.len() - 1];
+    if last[0] + last[1] < first[0] + first[1] {
+        corners.reverse();
+    }
+}
+
+fn grid_spacing(corners: &[[f64; 2]], board: &Checkerboard) -> f64 {
+    let mut total = 0.0;
+    let mut count = 0;
+    for r in 0..board.rows {
+        for c in 0..board.cols {
+            let p = corners[r * board.cols + c];
+            if c + 1 < board.cols {
+                total += norm(sub(corners[r * board.cols + c + 1], p));
+                count += 1;
+            }
+            if r + 1 < board.rows {
+                total += norm(sub(corners[(r + 1) * board.cols + c], p));
+                count += 1;
+            }
+        }
+    }
+    total / count as f64
+}
+
+/// Moves the corner to where the image gradients in its window are all
+/// orthogonal to the direction towards it.
+fn refine_corner(
+    image: &[f32],
+    width: usize,
+    height: usize,
+    corner: [f64; 2],
+    half: isize,
+) -> [f64; 2] {
+    let mut c = corner;
+    for _ in 0..20 {
+        let (cx, cy) = (c[0].round() as isize, c[1].round() as isize);
+        let (mut a, mut b, mut d) = (0.0, 0.0, 0.0);
+        let (mut bx, mut by) = (0.0, 0.0);
+        for y in cy - half..=cy + half {
+            for x in cx - half..=cx + half {
+                if x < 1 || y < 1 || x >= width as isize - 1 || y >= height as isize - 1 {
+                    continue;
+                }
+                let (ux, uy) = (x as usize, y as usize);
+                let gx = (image[uy * width + ux + 1] - image[uy * width + ux - 1]) as f64 / 2.0;
+                let gy = (image[(uy + 1) * width + ux] - image[(uy - 1) * width + ux]) as f64 / 2.0;
+                let (dx, dy) = (x as f64 - c[0], y as f64 - c[1]);
+                let w = (-(dx * dx + dy * dy) / (half * half) as f64).exp();
+                let (gxx, gxy, gyy) = (w * gx * gx, w * gx * gy, w * gy * gy);
+                a += gxx;
+                b += gxy;
+                d += gyy;
+                bx += gxx * x as f64 + gxy * y as f64;
+                by += gxy * x as f64 + gyy * y as f64;
+            }
+        }
+        let det = a * d - b * b;
+        if det.abs() < f64::EPSILON {
+            break;
+        }
+        let next = [(d * bx - b * by) / det, (a * by - b * bx) / det];
+        let moved = norm(sub(next, c));
+        if moved > half as f64 {
+            // the window lost the corner, keep the pixel estimate
+            return corner;
+        }
+        c = next;
+        if moved < 1e-3 {
+            break;
+        }
+    }
+    c
+}
+
+type Mat3 = [[f64; 3]; 3];
+
+fn mat_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+/// Translates the points to their centroid and scales them to an average
+/// distance of √2, returning the transform and its inverse.
+fn normalization(points: &[[f64; 2]]) -> (Mat3, Mat3) {
+    let n = points.len() as f64;
+    let (mx, my) = points
+        .iter()
+        .fold((0.0, 0.0), |(x, y), p| (x + p[0] / n, y + p[1] / n));
+    let mean_dist = points.iter().map(|p| norm(sub(*p, [mx, my]))).sum::<f64>() / n;
+    let s = std::f64::consts::SQRT_2 / mean_dist.max(f64::EPSILON);
+    (
+        [[s, 0.0, -s * mx], [0.0, s, -s * my], [0.0, 0.0, 1.0]],
+        [[1.0 / s, 0.0, mx], [0.0, 1.0 / s, my], [0.0, 0.0, 1.0]],
+    )
+}
+
+fn apply(m: &Mat3, p: [f64; 2]) -> [f64; 2] {
+    let w = m[2][0] * p[0] + m[2][1] * p[1] + m[2][2];
+    [
+        (m[0][0] * p[0] + m[0][1] * p[1] + m[0][2]) / w,
+        (m[1][0] * p[0] + m[1][1] * p[1] + m[1][2]) / w,
+    ]
+}
+
+/// Normalized DLT estimate of the homography mapping board to image points.
+fn homography(object: &[[f64; 2]], image: &[[f64; 2]]) -> Option<Mat3> {
+    let (to, _) = normalization(object);
+    let (ti, ti_inv) = normalization(image);
+
+    let mut ata = vec![0.0; 81];
+    for (o, i) in object.iter().zip(image) {
+        let [x, y] = apply(&to, *o);
+        let [u, v] = apply(&ti, *i);
+        let rows = [
+            [-x, -y, -1.0, 0.0, 0.0, 0.0, u * x, u * y, u],
+            [0.0, 0.0, 0.0, -x, -y, -1.0, v * x, v * y, v],
+        ];
+        for row in rows {
+            for a in 0..9 {
+                for b in 0..9 {
+                    ata[a * 9 + b] += row[a] * row[b];
+                }
+            }
+        }
+    }
+    let h = smallest_eigenvector(&ata, 9)?;
+    let hn = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], h[8]]];
+    let mut out = mat_mul(&mat_mul(&ti_inv, &hn), &to);
+    let scale = out[2][2];
+    if scale.abs() < f64::EPSILON {
+        return None;
+    }
+    out.iter_mut().flatten().for_each(|v| *v /= scale);
+    Some(out)
+}
+
+/// Zhang's closed form estimate of `[fx, fy, cx, cy]`, assuming square pixels
+/// without skew.
+fn closed_form_intrinsics(homographies: &[Mat3]) -> Option<[f64; 4]> {
+    let v = |h: &Mat3, i: usize, j: usize| {
+        [
+            h[0][i] * h[0][j],
+            h[0][i] * h[1][j] + h[1][i] * h[0][j],
+            h[1][i] * h[1][j],
+            h[2][i] * h[0][j] + h[0][i] * h[2][j],
+            h[2][i] * h[1][j] + h[1][i] * h[2][j],
+            h[2][i] * h[2][j],
+        ]
+    };
+    let mut vtv = vec![0.0; 36];
+    let mut add = |row: [f64; 6]| {
+        let n = row
+            .iter()
+            .map(|x| x * x)
+            .sum::<f64>()
+            .sqrt()
+            .max(f64::EPSILON);
+        for a in 0..6 {
+            for b in 0..6 {
+                vtv[a * 6 + b] += row[a] * row[b] / (n * n);
+            }
+        }
+    };
+    for h in homographies {
+        let (v11, v12, v22) = (v(h, 0, 0), v(h, 0, 1), v(h, 1, 1));
+        add(v12);
+        add(std::array::from_fn(|k| v11[k] - v22[k]));
+    }
+    add([0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+
+    let b = smallest_eigenvector(&vtv, 6)?;
+    let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+    let den = b11 * b22 - b12 * b12;
+    if den.abs() < f64::EPSILON || b11.abs() < f64::EPSILON {
+        return None;
+    }
+    let cy = (b12 * b13 - b11 * b23) / den;
+    let lambda = b33 - (b13 * b13 + cy * (b12 * b13 - b11 * b23)) / b11;
+    let (fx2, fy2) = (lambda / b11, lambda * b11 / den);
+    if fx2 <= 0.0 || fy2 <= 0.0 {
+        return None;
+    }
+    let (fx, fy) = (fx2.sqrt(), fy2.sqrt());
+    let cx = -b13 * fx2 / lambda;
+    Some([fx, fy, cx, cy])
+}
+
+/// Board pose `[rx, ry, rz, tx, ty, tz]` as a rotation vector and translation.
+fn pose_from_homography(k: &[f64; 4], h: &Mat3) -> [f64; 6] {
+    let [fx, fy, cx, cy] = *k;
+    let unproject = |c: usize| {
+        let y = (h[1][c] - cy * h[2][c]) / fy;
+        [(h[0][c] - cx * h[2][c]) / fx, y, h[2][c]]
+    };
+    let (h1, h2, h3) = (unproject(0), unproject(1), unproject(2));
+    let len = |v: [f64; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    let mut scale = 1.0 / len(h1);
+    if h3[2] * scale < 0.0 {
+        scale = -scale;
+    }
+    let r1 = h1.map(|x| x * scale);
+    let r1 = r1.map(|x| x / len(r1));
+    let r2 = h2.map(|x| x * scale);
+    let dot = r1[0] * r2[0] + r1[1] * r2[1] + r1[2] * r2[2];
+    let r2 = [
+        r2[0] - dot * r1[0],
+        r2[1] - dot * r1[1],
+        r2[2] - dot * r1[2],
+    ];
+    let r2 = r2.map(|x| x / len(r2));
+    let r3 = cross(r1, r2);
+    let t = h3.map(|x| x * scale);
+    let r = rotation_vector(&[
+        [r1[0], r2[0], r3[0]],
+        [r1[1], r2[1], r3[1]],
+        [r1[2], r2[2], r3[2]],
+    ]);
+    [r[0], r[1], r[2], t[0], t[1], t[2]]
+}
+
+pub(crate) fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn rotation_vector(r: &Mat3) -> [f64; 3] {
+    // through a quaternion, which stays well conditioned near 180°
+    let trace = r[0][0] + r[1][1] + r[2][2];
+    let (w, x, y, z) = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        (
+            s / 4.0,
+            (r[2][1] - r[1][2]) / s,
+            (r[0][2] - r[2][0]) / s,
+            (r[1][0] - r[0][1]) / s,
+        )
+    } else if r[0][0] > r[1][1] && r[0][0] > r[2][2] {
+        let s = (1.0 + r[0][0] - r[1][1] - r[2][2]).sqrt() * 2.0;
+        (
+            (r[2][1] - r[1][2]) / s,
+            s / 4.0,
+            (r[0][1] + r[1][0]) / s,
+            (r[0][2] + r[2][0]) / s,
+        )
+    } else if r[1][1] > r[2][2] {
+        let s = (1.0 + r[1][1] - r[0][0] - r[2][2]).sqrt() * 2.0;
+        (
+            (r[0][2] - r[2][0]) / s,
+            (r[0][1] + r[1][0]) / s,
+            s / 4.0,
+            (r[1][2] + r[2][1]) / s,
+        )
+    } else {
+        let s = (1.0 + r[2][2] - r[0][0] - r[1][1]).sqrt() * 2.0;
+        (
+            (r[1][0] - r[0][1]) / s,
+            (r[0][2] + r[2][0]) / s,
+            (r[1][2] + r[2][1]) / s,
+            s / 4.0,
+        )
+    };
+    let sin = (x * x + y * y + z * z).sqrt();
+    if sin < f64::EPSILON {
+        return [0.0; 3];
+    }
+    let angle = 2.0 * sin.atan2(w);
+    [x / sin * angle, y / sin * angle, z / sin * angle]
+}
+
+fn rotation_matrix(r: [f64; 3]) -> Mat3 {
+    let angle = (r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt();
+    if angle < f64::EPSILON {
+        return [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    }
+    let [x, y, z] = r.map(|v| v / angle);
+    let (s, c) = angle.sin_cos();
+    let t = 1.0 - c;
+    [
+        [t * x * x + c, t * x * y - s * z, t * x * z + s * y],
+        [t * x * y + s * z, t * y * y + c, t * y * z - s * x],
+        [t * x * z - s * y, t * y * z + s * x, t * z * z + c],
+    ]
+}
+
+/// `[fx, fy, cx, cy, k1, k2, p1, p2, k3]` and one pose per view.
+struct Params {
+    camera: [f64; 9],
+    poses: Vec<[f64; 6]>,
+}
+
+const CAMERA_PARAMS: usize = 9;
+const POSE_PARAMS: usize = 6;
+const BLOCK: usize = CAMERA_PARAMS + POSE_PARAMS;
+
+fn project(camera: &[f64], pose: &[f64], point: [f64; 2]) -> [f64; 2] {
+    let r = rotation_matrix([pose[0], pose[1], pose[2]]);
+    let p = [point[0], point[1], 0.0];
+    let c: [f64; 3] =
+        std::array::from_fn(|i| r[i][0] * p[0] + r[i][1] * p[1] + r[i][2] * p[2] + pose[3 + i]);
+    let distortion = Distortion {
+        k1: camera[4],
+        k2: camera[5],
+        p1: camera[6],
+        p2: camera[7],
+        k3: camera[8],
+    };
+    let (x, y) = distortion.distort(c[0] / c[2], c[1] / c[2]);
+    [camera[0] * x + camera[2], camera[1] * y + camera[3]]
+}
+
+fn view_residuals(
+    block: &[f64; BLOCK],
+    object: &[[f64; 2]],
+    image: &[[f64; 2]],
+    out: &mut Vec<f64>,
+) {
+    out.clear();
+    let (camera, pose) = block.split_at(CAMERA_PARAMS);
+    for (o, i) in object.iter().zip(image) {
+        let p = project(camera, pose, *o);
+        out.push(p[0] - i[0]);
+        out.push(p[1] - i[1]);
+    }
+}
+
+fn total_error(params: &Params, object: &[[f64; 2]], views: &[Vec<[f64; 2]>]) -> f64 {
+    let mut residuals = Vec::new();
+    params
+        .poses
+        .iter()
+        .zip(views)
+        .map(|(pose, image)| {
+            view_residuals(&block(&params.camera, pose), object, image, &mut residuals);
+            residuals.iter().map(|r| r * r).sum::<f64>()
+        })
+        .sum()
+}
+
+fn block(camera: &[f64; 9], pose: &[f64; 6]) -> [f64; BLOCK] {
+    std::array::from_fn(|i| {
+        if i < CAMERA_PARAMS {
+            camera[i]
+        } else {
+            pose[i - CAMERA_PARAMS]
+        }
+    })
+}
+
+/// Levenberg-Marquardt over every parameter, returns the final sum of squared errors.
+fn refine(params: &mut Params, object: &[[f64; 2]], views: &[Vec<[f64; 2]>]) -> f64 {
+    let n = CAMERA_PARAMS + POSE_PARAMS * views.len();
+    // index of each block parameter in the full parameter vector
+    let index = |view: usize, k: usize| {
+        if k < CAMERA_PARAMS {
+            k
+        } else {
+            CAMERA_PARAMS + view * POSE_PARAMS + k - CAMERA_PARAMS
+        }
+    };
+
+    let mut error = total_error(params, object, views);
+    let mut damping = 1e-3;
+    let mut residuals = Vec::new();
+    let (mut plus, mut minus) = (Vec::new(), Vec::new());
+    for _ in 0..MAX_ITERATIONS {
+        let mut jtj = vec![0.0; n * n];
+        let mut jtr = vec![0.0; n];
+        for (view, (pose, image)) in params.poses.iter().zip(views).enumerate() {
+            let base = block(&params.camera, pose);
+            view_residuals(&base, object, image, &mut residuals);
+            let jacobian: Vec<Vec<f64>> = (0..BLOCK)
+                .map(|k| {
+                    let step = 1e-6 * base[k].abs().max(1e-2);
+                    let mut p = base;
+                    p[k] = base[k] + step;
+                    view_residuals(&p, object, image, &mut plus);
+                    p[k] = base[k] - step;
+                    view_residuals(&p, object, image, &mut minus);
+                    plus.iter()
+                        .zip(&minus)
+                        .map(|(a, b)| (a - b) / (2.0 * step))
+                        .collect()
+                })
+                .collect();
+            for a in 0..BLOCK {
+                let ia = index(view, a);
+                jtr[ia] += jacobian[a]
+                    .iter()
+                    .zip(&residuals)
+                    .map(|(j, r)| j * r)
+                    .sum::<f64>();
+                for b in 0..BLOCK {
+                    let ib = index(view, b);
+                    jtj[ia * n + ib] += jacobian[a]
+                        .iter()
+                        .zip(&jacobian[b])
+                        .map(|(x, y)| x * y)
+                        .sum::<f64>();
+                }
+            }
+        }
+
+        let improved = loop {
+            let mut a = jtj.clone();
+            for i in 0..n {
+                a[i * n + i] += damping * jtj[i * n + i].max(1e-9);
+            }
+            let Some(delta) = solve_spd(&mut a, &jtr, n) else {
+                damping *= 10.0;
+                if damping > 1e12 {
+                    break None;
+                }
+                continue;
+            };
+            let candidate = Params {
+                camera: std::array::from_fn(|k| params.camera[k] - delta[k]),
+                poses: params
+                    .poses
+                    .iter()
+                    .enumerate()
+                    .map(|(v, pose)| {
+                        std::array::from_fn(|k| pose[k] - delta[index(v, CAMERA_PARAMS + k)])
+                    })
+                    .collect(),
+            };
+            let candidate_error = total_error(&candidate, object, views);
+            if candidate_error < error {
+                damping = (damping / 10.0).max(1e-12);
+                break Some((candidate, candidate_error));
+            }
+            damping *= 10.0;
+            if damping > 1e12 {
+                break None;
+            }
+        };
+
+        match improved {
+            Some((candidate, candidate_error)) => {
+                let converged = error - candidate_error < 1e-12 * error.max(1e-12);
+                *params = candidate;
+                error = candidate_error;
+                if converged {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    error
+}
+
+/// Solves `a x = b` for a symmetric positive definite `a` with a Cholesky
+/// decomposition, done in place.
+fn solve_spd(a: &mut [f64], b: &[f64], n: usize) -> Option<Vec<f64>> {
+    for j in 0..n {
+        let mut d = a[j * n + j];
+        for k in 0..j {
+            d -= a[j * n + k] * a[j * n + k];
+        }
+        if d <= 0.0 || !d.is_finite() {
+            return None;
+        }
+        let d = d.sqrt();
+        a[j * n + j] = d;
+        for i in j + 1..n {
+            let mut s = a[i * n + j];
+            for k in 0..j {
+                s -= a[i * n + k] * a[j * n + k];
+            }
+            a[i * n + j] = s / d;
+        }
+    }
+    let mut y = vec![0.0; n];
+    for i in 0..n {
+        let s: f64 = (0..i).map(|k| a[i * n + k] * y[k]).sum();
+        y[i] = (b[i] - s) / a[i * n + i];
+    }
+    let mut x = vec![0.0; n];
+    for i in (0..n).rev() {
+        let s: f64 = (i + 1..n).map(|k| a[k * n + i] * x[k]).sum();
+        x[i] = (y[i] - s) / a[i * n + i];
+    }
+    Some(x)
+}
+
+/// Eigenvector of the smallest eigenvalue of a symmetric matrix, with Jacobi rotations.
+pub(crate) fn smallest_eigenvector(matrix: &[f64], n: usize) -> Option<Vec<f64>> {
+    let mut a = matrix.to_vec();
+    let mut v = vec![0.0; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.0;
+    }
+    for _ in 0..100 {
+        let off: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * n + j] * a[i * n + j])
+            .sum();
+        let scale: f64 = (0..n).map(|i| a[i * n + i] * a[i * n + i]).sum();
+        if off <= 1e-30 * scale.max(f64::MIN_POSITIVE) {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[p * n + q];
+                if apq.abs() < f64::MIN_POSITIVE {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    let min = (0..n).min_by(|&i, &j| a[i * n + i].total_cmp(&a[j * n + j]))?;
+    let vector: Vec<f64> = (0..n).map(|k| v[k * n + min]).collect();
+    vector.iter().all(|x| x.is_finite()).then_some(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: [f64; 9] = [525.0, 520.0, 318.0, 243.0, -0.2, 0.08, 0.0, 0.0, 0.0];
+
+    // rotation vectors and translations of the board in each view
+    const POSES: [[f64; 6]; 6] = [
+        [0.1, -0.2, 0.05, -0.12, -0.08, 0.7],
+        [-0.3, 0.1, -0.1, -0.1, -0.07, 0.65],
+        [0.25, 0.3, 0.2, -0.13, -0.1, 0.75],
+        [-0.1, -0.35, -0.3, -0.09, -0.06, 0.8],
+        [0.35, 0.05, 0.4, -0.1, -0.12, 0.7],
+        [0.0, 0.0, 0.0, -0.11, -0.08, 0.6],
+    ];
+
+    fn calibrator(board: Checkerboard) -> Calibrator {
+        let object = board.object_points();
+        let mut calibrator = Calibrator::new(board, 640, 480);
+        for pose in &POSES {
+            let corners = object.iter().map(|p| project(&CAMERA, pose, *p)).collect();
+            calibrator.add_corners(corners).unwrap();
+        }
+        calibrator
+    }
+
+    #[test]
+    fn recovers_intrinsics_and_distortion() {
+        let calibration = calibrator(Checkerboard::new(9, 6, 0.025))
+            .calibrate()
+            .unwrap();
+        let Intrinsics { fx, fy, cx, cy, .. } = calibration.intrinsics;
+        assert!((fx - CAMERA[0]).abs() < 0.5, "fx {fx}");
+        assert!((fy - CAMERA[1]).abs() < 0.5, "fy {fy}");
+        assert!((cx - CAMERA[2]).abs() < 0.5, "cx {cx}");
+        assert!((cy - CAMERA[3]).abs() < 0.5, "cy {cy}");
+        assert!((calibration.distortion.k1 - CAMERA[4]).abs() < 0.01);
+        assert!((calibration.distortion.k2 - CAMERA[5]).abs() < 0.02);
+        assert!(calibration.rms_error < 1e-3);
+        assert_eq!(calibration.views, POSES.len());
+    }
+
+    // renders a board with antialiased edges, `to_board` maps a pixel to board
+    // coordinates in squares, with the inner corners at integer positions
+    fn render(
+        width: usize,
+        height: usize,
+        board: &Checkerboard,
+        to_board: impl Fn(f64, f64) -> [f64; 2],
+    ) -> Vec<u8> {
+        const SUBSAMPLES: usize = 4;
+        let mut gray = vec![0; width * height];
+        for (i, pixel) in gray.iter_mut().enumerate() {
+            let (x, y) = ((i % width) as f64, (i / width) as f64);
+            let mut sum = 0.0;
+            for s in 0..SUBSAMPLES * SUBSAMPLES {
+                let sx = x + ((s % SUBSAMPLES) as f64 + 0.5) / SUBSAMPLES as f64 - 0.5;
+                let sy = y + ((s / SUBSAMPLES) as f64 + 0.5) / SUBSAMPLES as f64 - 0.5;
+                let [bx, by] = to_board(sx, sy);
+                let inside = (-1.0..board.cols as f64).contains(&bx)
+                    && (-1.0..board.rows as f64).contains(&by);
+                let dark = inside && (bx.floor() + by.floor()) as i64 % 2 == 0;
+                sum += if dark { 40.0 } else { 200.0 };
+            }
+            *pixel = (sum / (SUBSAMPLES * SUBSAMPLES) as f64).round() as u8;
+        }
+        gray
+    }
+
+    fn assert_corners(found: &[[f64; 2]], expected: &[[f64; 2]]) {
+        assert_eq!(found.len(), expected.len());
+        for (i, (f, e)) in found.iter().zip(expected).enumerate() {
+            let error = norm(sub(*f, *e));
+            assert!(error < 0.2, "corner {i} at {f:?}, expected {e:?}");
+        }
+    }
+
+    // a board of 30px squares rotated by `angle` about its centre at (160.3, 120.6)
+    fn rotated(angle: f64) -> (Checkerboard, Vec<u8>, Vec<[f64; 2]>) {
+        let board = Checkerboard::new(7, 5, 1.0);
+        let (sin, cos) = angle.sin_cos();
+        let (side, centre) = (30.0, [160.3, 120.6]);
+        let middle = [3.0, 2.0];
+        let gray = render(320, 240, &board, |x, y| {
+            let (dx, dy) = ((x - centre[0]) / side, (y - centre[1]) / side);
+            [
+                middle[0] + cos * dx + sin * dy,
+                middle[1] - sin * dx + cos * dy,
+            ]
+        });
+        let expected = board
+            .object_points()
+            .into_iter()
+            .map(|[bx, by]| {
+                let (dx, dy) = ((bx - middle[0]) * side, (by - middle[1]) * side);
+                [
+                    centre[0] + cos * dx - sin * dy,
+                    centre[1] + sin * dx + cos * dy,
+                ]
+            })
+            .collect();
+        (board, gray, expected)
+    }
+
+    #[test]
+    fn finds_an_upright_board() {
+        let (board, gray, expected) = rotated(0.0);
+        let found = find_corners(&gray, 320, 240, &board).unwrap();
+        assert_corners(&found, &expected);
+    }
+
+    #[test]
+    fn finds_a_rotated_board() {
+        let (board, gray, expected) = rotated(25f64.to_radians());
+        let found = find_corners(&gray, 320, 240, &board).unwrap();
+        assert_corners(&found, &expected);
+    }
+
+    #[test]
+    fn finds_a_board_under_perspective_and_distortion() {
+        let board = Checkerboard::new(9, 6, 0.025);
+        let pose = &POSES[0];
+        let r = rotation_matrix([pose[0], pose[1], pose[2]]);
+        let gray = render(640, 480, &board, |u, v| {
+            // undistort the pixel, then intersect its ray with the board plane
+            let (xd, yd) = ((u - CAMERA[2]) / CAMERA[0], (v - CAMERA[3]) / CAMERA[1]);
+            let (mut x, mut y) = (xd, yd);
+            for _ in 0..20 {
+                let r2 = x * x + y * y;
+                let radial = 1.0 + r2 * (CAMERA[4] + r2 * CAMERA[5]);
+                (x, y) = (xd / radial, yd / radial);
+            }
+            let columns = [
+                [r[0][0], r[1][0], r[2][0]],
+                [r[0][1], r[1][1], r[2][1]],
+                [-x, -y, -1.0],
+            ];
+            let rhs = [-pose[3], -pose[4], -pose[5]];
+            let det = |m: [[f64; 3]; 3]| {
+                m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                    - m[1][0] * (m[0][1] * m[2][2] - m[0][2] * m[2][1])
+                    + m[2][0] * (m[0][1] * m[1][2] - m[0][2] * m[1][1])
+            };
+            let d = det(columns);
+            let solve = |k: usize| {
+                let mut m = columns;
+                m[k] = rhs;
+                det(m) / d / board.square_size
+            };
+            [solve(0), solve(1)]
+        });
+        let expected: Vec<_> = board
+            .object_points()
+            .into_iter()
+            .map(|p| project(&CAMERA, pose, p))
+            .collect();
+        let found = find_corners(&gray, 640, 480, &board).unwrap();
+        assert_corners(&found, &expected);
+    }
+
+    #[test]
+    fn rejects_a_partially_occluded_board() {
+        let (board, mut gray, expected) = rotated(0.0);
+        // cover the rightmost column of inner corners
+        let left = expected[board.cols - 1][0] as usize - 10;
+        for row in gray.chunks_mut(320) {
+            row[left..].fill(128);
+        }
+        assert!(find_corners(&gray, 320, 240, &board).is_none());
+    }
+
+    #[test]
+    fn rejects_wrong_corner_count() {
+        let mut calibrator = Calibrator::new(Checkerboard::new(9, 6, 0.025), 640, 480);
+        let err = calibrator.add_corners(vec![[0.0, 0.0]; 53]).unwrap_err();
+        assert!(matches!(
+            err,
+            FreenectError::Algorithm(AlgorithmError::InvalidCornerCount {
+                expected: 54,
+                got: 53
+            })
+        ));
+    }
+
+    #[test]
+    fn needs_three_views() {
+        let mut calibrator = calibrator(Checkerboard::new(9, 6, 0.025));
+        calibrator.views.truncate(2);
+        assert!(matches!(
+            calibrator.calibrate(),
+            Err(FreenectError::Algorithm(
+                AlgorithmError::NotEnoughCalibrationViews(2)
+            ))
+        ));
+    }
+}
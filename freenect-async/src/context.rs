@@ -1,12 +1,19 @@
 use std::{
-    ffi::CStr,
+    collections::BTreeMap,
+    ffi::{CStr, CString},
     mem::{ManuallyDrop, MaybeUninit},
     ptr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
 };
 
-use crate::{device::FreenectDevice, FreenectError};
+use bitflags::bitflags;
 
-pub trait FreenectDeviceMode {}
+use crate::{
+    device::FreenectDevice, hotplug::HotplugStream, stream::Backpressure, FreenectError,
+};
+
+pub trait FreenectDeviceMode: 'static {}
 
 pub enum FreenectInitialized {}
 
@@ -38,9 +45,30 @@ impl FreenectDeviceMode for FreenectReadyAll {}
 
 impl FreenectDeviceReady for FreenectReadyAll {}
 
+/// Subdevices are chosen at runtime with a [`Subdevices`] set, so every
+/// video and motor method is available but fails with
+/// [`FreenectError::SubdeviceNotSelected`] if its subdevice wasn't selected.
+pub enum FreenectReadyDynamic {}
+
+impl FreenectDeviceMode for FreenectReadyDynamic {}
+
+impl FreenectDeviceReady for FreenectReadyDynamic {}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Subdevices: u32 {
+        const MOTOR = freenect_sys::freenect_device_flags_FREENECT_DEVICE_MOTOR;
+        const CAMERA = freenect_sys::freenect_device_flags_FREENECT_DEVICE_CAMERA;
+        const AUDIO = freenect_sys::freenect_device_flags_FREENECT_DEVICE_AUDIO;
+    }
+}
+
 #[derive(Debug)]
 pub struct FreenectContext<M: FreenectDeviceMode> {
     pub(crate) inner: *mut freenect_sys::freenect_context,
+    pub(crate) subdevices: Subdevices,
+    // shared with the frame slots of every device opened from this context
+    pub(crate) backpressure: Arc<Backpressure>,
 
     pub(crate) marker: std::marker::PhantomData<M>,
 }
@@ -49,61 +77,57 @@ impl FreenectContext<FreenectInitialized> {
     pub fn new() -> Result<Self, FreenectError> {
         unsafe {
             let mut inner = MaybeUninit::uninit();
-            if freenect_sys::freenect_init(inner.as_mut_ptr(), ptr::null_mut()) < 0 {
-                return Err(FreenectError::ContextCreationError);
+            let res = freenect_sys::freenect_init(inner.as_mut_ptr(), ptr::null_mut());
+            if res < 0 {
+                return Err(FreenectError::ContextCreationError(res.into()));
             }
             let inner = inner.assume_init();
+            let subdevices =
+                Subdevices::from_bits_truncate(freenect_sys::freenect_enabled_subdevices(inner));
             Ok(Self {
                 inner,
+                subdevices,
+                backpressure: Arc::default(),
                 marker: std::marker::PhantomData,
             })
         }
     }
 
     pub fn setup_video(self) -> FreenectContext<FreenectReadyVideo> {
-        unsafe {
-            freenect_sys::freenect_select_subdevices(
-                self.inner,
-                freenect_sys::freenect_device_flags_FREENECT_DEVICE_CAMERA,
-            )
-        };
-        FreenectContext {
-            inner: self.into_handle(),
-            marker: std::marker::PhantomData,
-        }
+        self.select(Subdevices::CAMERA)
     }
 
     pub fn setup_video_motors(self) -> FreenectContext<FreenectReadyVideoMotors> {
-        unsafe {
-            freenect_sys::freenect_select_subdevices(
-                self.inner,
-                freenect_sys::freenect_device_flags_FREENECT_DEVICE_CAMERA
-                    | freenect_sys::freenect_device_flags_FREENECT_DEVICE_MOTOR,
-            )
-        };
-        FreenectContext {
-            inner: self.into_handle(),
-            marker: std::marker::PhantomData,
-        }
+        self.select(Subdevices::CAMERA | Subdevices::MOTOR)
     }
 
     pub fn setup_motors(self) -> FreenectContext<FreenectReadyMotors> {
-        unsafe {
-            freenect_sys::freenect_select_subdevices(
-                self.inner,
-                freenect_sys::freenect_device_flags_FREENECT_DEVICE_MOTOR,
-            )
-        };
+        self.select(Subdevices::MOTOR)
+    }
+
+    pub fn setup_all(self) -> FreenectContext<FreenectReadyAll> {
+        // do not call freenect_select_subdevices, as all subdevices are selected by default
+        let subdevices = self.subdevices;
+        let (inner, backpressure) = self.into_parts();
         FreenectContext {
-            inner: self.into_handle(),
+            inner,
+            subdevices,
+            backpressure,
             marker: std::marker::PhantomData,
         }
     }
 
-    pub fn setup_all(self) -> FreenectContext<FreenectReadyAll> {
-        // do not call freenect_select_subdevices, as all subdevices are selected by default
+    pub fn setup_dynamic(self, subdevices: Subdevices) -> FreenectContext<FreenectReadyDynamic> {
+        self.select(subdevices)
+    }
+
+    fn select<N: FreenectDeviceReady>(self, subdevices: Subdevices) -> FreenectContext<N> {
+        unsafe { freenect_sys::freenect_select_subdevices(self.inner, subdevices.bits()) };
+        let (inner, backpressure) = self.into_parts();
         FreenectContext {
-            inner: self.into_handle(),
+            inner,
+            subdevices,
+            backpressure,
             marker: std::marker::PhantomData,
         }
     }
@@ -113,14 +137,80 @@ impl<M> FreenectContext<M>
 where
     M: FreenectDeviceMode,
 {
+    pub fn selected_subdevices(&self) -> Subdevices {
+        self.subdevices
+    }
+
     pub fn list_devices(&self) -> Result<u32, FreenectError> {
         let res = unsafe { freenect_sys::freenect_num_devices(self.inner) };
         if res < 0 {
-            return Err(FreenectError::DeviceListError);
+            return Err(FreenectError::DeviceListError(res.into()));
         }
         Ok(res as u32)
     }
 
+    /// Serial numbers of the connected devices that report one.
+    pub fn device_attributes(&self) -> Result<Vec<String>, FreenectError> {
+        Ok(self.camera_serials()?.into_iter().flatten().collect())
+    }
+
+    /// Serial number of every connected device, in the order they are opened by index.
+    pub(crate) fn camera_serials(&self) -> Result<Vec<Option<String>>, FreenectError> {
+        unsafe {
+            let mut list = ptr::null_mut();
+            let res = freenect_sys::freenect_list_device_attributes(self.inner, &mut list);
+            if res < 0 {
+                return Err(FreenectError::DeviceListError(res.into()));
+            }
+
+            let mut serials = Vec::with_capacity(res as usize);
+            let mut attributes = list;
+            while !attributes.is_null() {
+                let serial = (*attributes).camera_serial;
+                serials.push(
+                    (!serial.is_null())
+                        .then(|| CStr::from_ptr(serial).to_string_lossy().into_owned()),
+                );
+                attributes = (*attributes).next;
+            }
+            freenect_sys::freenect_free_device_attributes(list);
+
+            Ok(serials)
+        }
+    }
+
+    pub fn hotplug_events(
+        &self,
+        interval: Duration,
+    ) -> Result<HotplugStream<'_, M>, FreenectError> {
+        HotplugStream::new(self, interval)
+    }
+
+    /// Processes pending USB events for every device opened from this context,
+    /// handing frames over to their streams.
+    pub(crate) fn process_events(&self) -> Result<(), FreenectError> {
+        let res = unsafe { freenect_sys::freenect_process_events(self.inner) };
+        if res < 0 {
+            return Err(FreenectError::EventProcessingError(res.into()));
+        }
+        Ok(())
+    }
+
+    /// Like [`process_events`](Self::process_events), but only handles the
+    /// events already pending instead of waiting for one.
+    pub(crate) fn process_pending_events(&self) -> Result<(), FreenectError> {
+        let mut timeout = freenect_sys::timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+        let res =
+            unsafe { freenect_sys::freenect_process_events_timeout(self.inner, &mut timeout) };
+        if res < 0 {
+            return Err(FreenectError::EventProcessingError(res.into()));
+        }
+        Ok(())
+    }
+
     pub fn set_log_level(&self, level: FreenectLogLevel) {
         unsafe {
             freenect_sys::freenect_set_log_level(self.inner, level as u32);
@@ -129,37 +219,50 @@ where
 
     pub fn set_log_callback(&self, callback: Option<LogCallback>) {
         unsafe extern "C" fn c_callback_wrapper(
-            _dev: *mut freenect_sys::freenect_context,
+            ctx: *mut freenect_sys::freenect_context,
             level: freenect_sys::freenect_loglevel,
             msg: *const std::os::raw::c_char,
         ) {
-            let msg_str = std::ffi::CStr::from_ptr(msg);
-            let level = FreenectLogLevel::try_from(level).unwrap_or_default();
-            if let Some(cb) = LOG_CALLBACK {
-                cb(level, msg_str)
+            // clone the handler out so it may change log callbacks itself
+            let handler = log_handlers().get(&(ctx as usize)).cloned();
+            if let Some(handler) = handler {
+                let msg = CStr::from_ptr(msg).to_string_lossy();
+                let level = FreenectLogLevel::try_from(level).unwrap_or_default();
+                handler(level, msg.trim_end())
             }
         }
 
-        unsafe {
-            match callback {
-                None => freenect_sys::freenect_set_log_callback(self.inner, None),
-                Some(c) => {
-                    freenect_sys::freenect_set_log_callback(self.inner, Some(c_callback_wrapper));
-                    LOG_CALLBACK = Some(c);
-                }
+        let key = self.inner as usize;
+        match callback {
+            None => {
+                unsafe { freenect_sys::freenect_set_log_callback(self.inner, None) };
+                log_handlers().remove(&key);
+            }
+            Some(c) => {
+                log_handlers().insert(key, Arc::from(c));
+                unsafe {
+                    freenect_sys::freenect_set_log_callback(self.inner, Some(c_callback_wrapper))
+                };
             }
         }
     }
 
-    fn into_handle(self) -> *mut freenect_sys::freenect_context {
+    fn into_parts(self) -> (*mut freenect_sys::freenect_context, Arc<Backpressure>) {
         let m = ManuallyDrop::new(self);
-        m.inner
+        (m.inner, unsafe { ptr::read(&m.backpressure) })
     }
 }
 
-type LogCallback = fn(level: FreenectLogLevel, msg: &CStr);
-// FIXME: find a way to not use a static mut here
-static mut LOG_CALLBACK: Option<LogCallback> = None;
+pub type LogCallback = Box<dyn Fn(FreenectLogLevel, &str) + Send + Sync>;
+
+type LogHandler = Arc<dyn Fn(FreenectLogLevel, &str) + Send + Sync>;
+
+// libfreenect contexts have no user pointer, so handlers are looked up by context
+static LOG_HANDLERS: Mutex<BTreeMap<usize, LogHandler>> = Mutex::new(BTreeMap::new());
+
+fn log_handlers() -> MutexGuard<'static, BTreeMap<usize, LogHandler>> {
+    LOG_HANDLERS.lock().unwrap_or_else(|e| e.into_inner())
+}
 
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -197,27 +300,91 @@ impl<M> FreenectContext<M>
 where
     M: FreenectDeviceReady,
 {
-    pub fn open_device(&mut self, index: u32) -> Result<FreenectDevice<M>, FreenectError> {
+    pub fn open_device(&self, index: u32) -> Result<FreenectDevice<M>, FreenectError> {
         if index >= self.list_devices()? {
             return Err(FreenectError::DeviceNotFound(index));
         }
+        // devices are enumerated in the same order they are opened by index
+        let serial = self
+            .camera_serials()
+            .ok()
+            .and_then(|serials| serials.into_iter().nth(index as usize))
+            .flatten();
         unsafe {
             let mut dev = MaybeUninit::uninit();
-            if freenect_sys::freenect_open_device(self.inner, dev.as_mut_ptr(), index as i32) < 0 {
-                return Err(FreenectError::OpenDeviceError(index));
+            self.reselect_subdevices();
+            let res =
+                freenect_sys::freenect_open_device(self.inner, dev.as_mut_ptr(), index as i32);
+            if res < 0 {
+                return Err(FreenectError::OpenDeviceError(index, res.into()));
             }
             let dev = dev.assume_init();
-            Ok(FreenectDevice {
-                inner: dev,
-                marker: self.marker,
-                context: self,
-            })
+            Ok(FreenectDevice::new(self, dev, serial, self.opened_subdevices()))
+        }
+    }
+
+    pub fn open_device_by_serial(
+        &self,
+        serial: &str,
+    ) -> Result<FreenectDevice<'_, M>, FreenectError> {
+        let not_found = || FreenectError::DeviceNotFoundBySerial(serial.to_string());
+        if !self.device_attributes()?.iter().any(|s| s == serial) {
+            return Err(not_found());
         }
+        let c_serial = CString::new(serial).map_err(|_| not_found())?;
+        unsafe {
+            let mut dev = MaybeUninit::uninit();
+            self.reselect_subdevices();
+            let res = freenect_sys::freenect_open_device_by_camera_serial(
+                self.inner,
+                dev.as_mut_ptr(),
+                c_serial.as_ptr(),
+            );
+            if res < 0 {
+                return Err(FreenectError::OpenDeviceBySerialError(
+                    serial.to_string(),
+                    res.into(),
+                ));
+            }
+            let dev = dev.assume_init();
+            Ok(FreenectDevice::new(
+                self,
+                dev,
+                Some(serial.to_string()),
+                self.opened_subdevices(),
+            ))
+        }
+    }
+
+    /// Like [`open_device`](Self::open_device), but fails if any of the
+    /// subdevices selected for this context could not be claimed.
+    pub fn open_device_strict(&self, index: u32) -> Result<FreenectDevice<'_, M>, FreenectError> {
+        let required = self.subdevices;
+        let dev = self.open_device(index)?;
+        let missing = required.difference(dev.opened_subdevices());
+        if !missing.is_empty() {
+            return Err(FreenectError::SubdeviceNotOpened(missing));
+        }
+        Ok(dev)
+    }
+
+    // libfreenect drops the subdevices it fails to claim from the context's
+    // selection, which would leave them out for every device opened afterwards
+    fn reselect_subdevices(&self) {
+        unsafe { freenect_sys::freenect_select_subdevices(self.inner, self.subdevices.bits()) };
+    }
+
+    // only meaningful right after opening a device
+    fn opened_subdevices(&self) -> Subdevices {
+        let flags = unsafe { freenect_sys::freenect_enabled_subdevices(self.inner) };
+        Subdevices::from_bits_truncate(flags) & self.subdevices
     }
 }
 
 impl<M: FreenectDeviceMode> Drop for FreenectContext<M> {
     fn drop(&mut self) {
+        // remove first, another context could get the same address once this one is freed
+        log_handlers().remove(&(self.inner as usize));
         unsafe {
             freenect_sys::freenect_shutdown(self.inner);
         }
@@ -0,0 +1,204 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::{Condvar, Mutex, MutexGuard, OnceLock},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// Runtime-agnostic timer. Pending delays register their waker with a single
+/// timer thread shared by every delay, which wakes them at their deadline.
+#[derive(Debug)]
+pub(crate) struct Delay {
+    deadline: Instant,
+    id: Option<u64>,
+}
+
+impl Delay {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + duration,
+            id: None,
+        }
+    }
+
+    pub(crate) fn poll_elapsed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            if let Some(id) = self.id.take() {
+                timer().cancel(id);
+            }
+            return Poll::Ready(());
+        }
+
+        match self.id {
+            Some(id) => timer().update(id, cx.waker()),
+            None => self.id = Some(timer().schedule(self.deadline, cx.waker().clone())),
+        }
+        Poll::Pending
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.get_mut().poll_elapsed(cx)
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            timer().cancel(id);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Timers {
+    next_id: u64,
+    // entries of cancelled delays stay until their deadline and are skipped then
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    wakers: HashMap<u64, Waker>,
+}
+
+struct Timer {
+    timers: Mutex<Timers>,
+    changed: Condvar,
+}
+
+impl Timer {
+    fn lock(&self) -> MutexGuard<'_, Timers> {
+        self.timers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn schedule(&self, deadline: Instant, waker: Waker) -> u64 {
+        let mut timers = self.lock();
+        let id = timers.next_id;
+        timers.next_id += 1;
+        let earliest =
+            !matches!(timers.deadlines.peek(), Some(Reverse((first, _))) if *first <= deadline);
+        timers.deadlines.push(Reverse((deadline, id)));
+        timers.wakers.insert(id, waker);
+        if earliest {
+            self.changed.notify_one();
+        }
+        id
+    }
+
+    fn update(&self, id: u64, waker: &Waker) {
+        if let Some(registered) = self.lock().wakers.get_mut(&id) {
+            if !registered.will_wake(waker) {
+                registered.clone_from(waker);
+            }
+        }
+    }
+
+    fn cancel(&self, id: u64) {
+        self.lock().wakers.remove(&id);
+    }
+
+    fn run(&self) {
+        let mut timers = self.lock();
+        loop {
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            while let Some(&Reverse((deadline, id))) = timers.deadlines.peek() {
+                if deadline > now {
+                    break;
+                }
+                timers.deadlines.pop();
+                expired.extend(timers.wakers.remove(&id));
+            }
+            if !expired.is_empty() {
+                // wake outside the lock, a waker may poll its delay right away
+                drop(timers);
+                expired.into_iter().for_each(Waker::wake);
+                timers = self.lock();
+                continue;
+            }
+
+            timers = match timers.deadlines.peek() {
+                Some(&Reverse((deadline, _))) => {
+                    let timeout = deadline.saturating_duration_since(now);
+                    self.changed
+                        .wait_timeout(timers, timeout)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self.changed.wait(timers).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        // the thread waits for the initialization to finish before running
+        std::thread::Builder::new()
+            .name("freenect-timer".into())
+            .spawn(|| timer().run())
+            .expect("failed to spawn the timer thread");
+        Timer {
+            timers: Mutex::default(),
+            changed: Condvar::new(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::Wake,
+    };
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn poll(delay: &mut Delay, waker: &Arc<CountingWaker>) -> Poll<()> {
+        let waker = Waker::from(waker.clone());
+        delay.poll_elapsed(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn wakes_every_delay_at_its_deadline() {
+        let wakers: Vec<_> = (0..20)
+            .map(|_| Arc::new(CountingWaker::default()))
+            .collect();
+        let mut delays: Vec<_> = (0..20)
+            .map(|i| Delay::new(Duration::from_millis(20 + i % 4)))
+            .collect();
+        for (delay, waker) in delays.iter_mut().zip(&wakers) {
+            assert!(poll(delay, waker).is_pending());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        for (delay, waker) in delays.iter_mut().zip(&wakers) {
+            assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+            assert!(poll(delay, waker).is_ready());
+        }
+    }
+
+    #[test]
+    fn dropped_delays_are_not_woken() {
+        let waker = Arc::new(CountingWaker::default());
+        let mut delay = Delay::new(Duration::from_millis(10));
+        assert!(poll(&mut delay, &waker).is_pending());
+        drop(delay);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(waker.0.load(Ordering::SeqCst), 0);
+    }
+}
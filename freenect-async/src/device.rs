@@ -1,25 +1,179 @@
-use std::mem::ManuallyDrop;
+use std::{cell::Cell, mem::ManuallyDrop, sync::Arc};
 
-use crate::context::{FreenectContext, FreenectDeviceReady};
+use crate::{
+    context::{FreenectContext, FreenectDeviceReady, Subdevices},
+    stream::{Backpressure, FrameSlot},
+    video::CameraFlags,
+    FreenectError,
+};
+
+const MICROSOFT_VENDOR_ID: u16 = 0x045e;
+// newer units report the serial of their audio subdevice, so every subdevice's
+// product ID is listed: camera, audio, motor. The model 1473 has no motor of its own.
+const XBOX_360_PRODUCT_IDS: [u16; 3] = [0x02ae, 0x02ad, 0x02b0];
+// the audio subdevice changes product ID with the firmware it runs
+const KINECT_FOR_WINDOWS_PRODUCT_IDS: [u16; 5] = [0x02bf, 0x02be, 0x02c3, 0x02bb, 0x02c2];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KinectModel {
+    /// The original model 1414 and its later revision, the model 1473.
+    Xbox360,
+    /// The model 1517, the only one with near mode.
+    KinectForWindows,
+}
+
+impl KinectModel {
+    pub fn from_product_id(product_id: u16) -> Option<Self> {
+        if XBOX_360_PRODUCT_IDS.contains(&product_id) {
+            Some(KinectModel::Xbox360)
+        } else if KINECT_FOR_WINDOWS_PRODUCT_IDS.contains(&product_id) {
+            Some(KinectModel::KinectForWindows)
+        } else {
+            None
+        }
+    }
+}
+
+/// Per-device state reachable from the libfreenect callbacks through the user pointer.
+#[derive(Debug)]
+pub(crate) struct DeviceShared {
+    pub(crate) video: FrameSlot<u8>,
+    pub(crate) depth: FrameSlot<u16>,
+    // flags set through this device, libfreenect has no way to read them
+    pub(crate) flags: Cell<CameraFlags>,
+    pub(crate) known_flags: Cell<CameraFlags>,
+}
+
+impl DeviceShared {
+    fn new(backpressure: &Arc<Backpressure>) -> Self {
+        Self {
+            video: FrameSlot::new(backpressure.clone()),
+            depth: FrameSlot::new(backpressure.clone()),
+            flags: Cell::default(),
+            known_flags: Cell::default(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct FreenectDevice<'a, D: FreenectDeviceReady> {
-    pub context: &'a mut FreenectContext<D>,
+    pub context: &'a FreenectContext<D>,
     pub(crate) inner: *mut freenect_sys::freenect_device,
+    pub(crate) serial: Option<String>,
+    pub(crate) subdevices: Subdevices,
+    // boxed so its address stays valid while registered as the user pointer
+    pub(crate) shared: Box<DeviceShared>,
     pub(crate) marker: std::marker::PhantomData<D>,
 }
 
 impl<'a, D: FreenectDeviceReady> Drop for FreenectDevice<'a, D> {
     fn drop(&mut self) {
         unsafe {
+            freenect_sys::freenect_set_user(self.inner, std::ptr::null_mut());
             freenect_sys::freenect_close_device(self.inner);
         }
     }
 }
 
 impl<'a, D: FreenectDeviceReady> FreenectDevice<'a, D> {
+    pub(crate) fn new(
+        context: &'a FreenectContext<D>,
+        inner: *mut freenect_sys::freenect_device,
+        serial: Option<String>,
+        subdevices: Subdevices,
+    ) -> Self {
+        let shared = Box::new(DeviceShared::new(&context.backpressure));
+        unsafe {
+            let user = &*shared as *const DeviceShared as *mut std::os::raw::c_void;
+            freenect_sys::freenect_set_user(inner, user);
+        }
+        Self {
+            context,
+            inner,
+            serial,
+            subdevices,
+            shared,
+            marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// Checks whether the device is still plugged in by re-enumerating devices,
+    /// which blocks on libusb. Fails with [`FreenectError::UnknownSerial`] for
+    /// devices without a serial number, as there is nothing to look them up by.
+    pub fn is_connected(&self) -> Result<bool, FreenectError> {
+        let serial = self.serial.as_ref().ok_or(FreenectError::UnknownSerial)?;
+        Ok(self.context.device_attributes()?.contains(serial))
+    }
+
+    /// The subdevices that were claimed when this device was opened, out of the
+    /// ones selected for the context.
+    pub fn opened_subdevices(&self) -> Subdevices {
+        self.subdevices
+    }
+
+    /// Identifies the model from the USB product ID of the device with this
+    /// serial, which libfreenect doesn't expose. Read from sysfs, so only
+    /// available on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn model(&self) -> Result<KinectModel, FreenectError> {
+        self.serial
+            .as_deref()
+            .and_then(usb_product_id)
+            .and_then(KinectModel::from_product_id)
+            .ok_or(FreenectError::UnknownModel)
+    }
+
+    pub(crate) fn require(&self, subdevices: Subdevices) -> Result<(), FreenectError> {
+        let missing = subdevices.difference(self.context.subdevices);
+        if !missing.is_empty() {
+            return Err(FreenectError::SubdeviceNotSelected(missing));
+        }
+        Ok(())
+    }
+
     fn into_handle(self) -> *mut freenect_sys::freenect_device {
         let m = ManuallyDrop::new(self);
         m.inner
     }
 }
+
+#[cfg(target_os = "linux")]
+fn usb_product_id(serial: &str) -> Option<u16> {
+    let read_hex = |path: &std::path::Path, name: &str| {
+        let value = std::fs::read_to_string(path.join(name)).ok()?;
+        u16::from_str_radix(value.trim(), 16).ok()
+    };
+    std::fs::read_dir("/sys/bus/usb/devices")
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| read_hex(path, "idVendor") == Some(MICROSOFT_VENDOR_ID))
+        .filter(|path| {
+            std::fs::read_to_string(path.join("serial")).is_ok_and(|s| s.trim() == serial)
+        })
+        .find_map(|path| read_hex(&path, "idProduct"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_product_ids_to_models() {
+        // camera and audio of a model 1473
+        for id in [0x02ae, 0x02ad] {
+            assert_eq!(KinectModel::from_product_id(id), Some(KinectModel::Xbox360));
+        }
+        for id in [0x02bf, 0x02be, 0x02c2] {
+            assert_eq!(
+                KinectModel::from_product_id(id),
+                Some(KinectModel::KinectForWindows)
+            );
+        }
+        assert_eq!(KinectModel::from_product_id(0x0000), None);
+    }
+}
@@ -0,0 +1,568 @@
+use std::collections::VecDeque;
+
+use crate::{
+    formats::{FreenectDepthFormat, FreenectFormat, FreenectVideoMode},
+    FreenectError,
+};
+
+/// A depth frame in millimetres, where 0 marks pixels without a reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepthImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u16>,
+}
+
+impl DepthImage {
+    pub fn new(width: usize, height: usize, data: Vec<u16>) -> Result<Self, FreenectError> {
+        if data.len() != width * height {
+            return Err(FreenectError::BadVideoFormat);
+        }
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Copies a frame from a [`DepthStream`](crate::stream::DepthStream) started
+    /// in one of the millimetre formats.
+    pub fn from_frame(mode: &FreenectVideoMode, data: &[u16]) -> Result<Self, FreenectError> {
+        match mode.format {
+            FreenectFormat::Depth(
+                FreenectDepthFormat::DepthMillimeters | FreenectDepthFormat::DepthRegistered,
+            ) => {}
+            _ => return Err(FreenectError::BadVideoFormat),
+        }
+        let len = mode.width as usize * mode.height as usize;
+        if data.len() < len {
+            return Err(FreenectError::BadVideoFormat);
+        }
+        Self::new(
+            mode.width as usize,
+            mode.height as usize,
+            data[..len].to_vec(),
+        )
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u16 {
+        self.data[y * self.width + x]
+    }
+
+    /// Fraction of pixels without a reading.
+    pub fn hole_fraction(&self) -> f64 {
+        if self.data.is_empty() {
+            return 0.0;
+        }
+        self.data.iter().filter(|&&d| d == 0).count() as f64 / self.data.len() as f64
+    }
+}
+
+/// One step of a [`FilterPipeline`], filtering a frame in place.
+pub trait DepthFilter {
+    fn apply(&mut self, image: &mut DepthImage);
+
+    /// Forgets state carried between frames, e.g. after the camera moved.
+    fn reset(&mut self) {}
+}
+
+/// Filters applied in order to every frame.
+///
+/// A typical order is speckle removal, median, hole filling, bilateral and
+/// finally temporal smoothing.
+#[derive(Default)]
+pub struct FilterPipeline {
+    filters: Vec<Box<dyn DepthFilter + Send>>,
+}
+
+impl std::fmt::Debug for FilterPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilterPipeline")
+            .field("filters", &self.filters.len())
+            .finish()
+    }
+}
+
+impl FilterPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, filter: impl DepthFilter + Send + 'static) -> Self {
+        self.push(filter);
+        self
+    }
+
+    pub fn push(&mut self, filter: impl DepthFilter + Send + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+impl DepthFilter for FilterPipeline {
+    fn apply(&mut self, image: &mut DepthImage) {
+        for filter in &mut self.filters {
+            filter.apply(image);
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+    }
+}
+
+/// Median of the valid pixels in a square window. Holes are left untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MedianFilter {
+    pub radius: usize,
+}
+
+impl Default for MedianFilter {
+    fn default() -> Self {
+        Self { radius: 1 }
+    }
+}
+
+impl DepthFilter for MedianFilter {
+    fn apply(&mut self, image: &mut DepthImage) {
+        let (width, height) = (image.width, image.height);
+        let src = image.data.clone();
+        let mut window = Vec::with_capacity((2 * self.radius + 1).pow(2));
+        for y in 0..height {
+            for x in 0..width {
+                if src[y * width + x] == 0 {
+                    continue;
+                }
+                window.clear();
+                for wy in y.saturating_sub(self.radius)..(y + self.radius + 1).min(height) {
+                    let row = &src[wy * width..(wy + 1) * width];
+                    let xs = x.saturating_sub(self.radius)..(x + self.radius + 1).min(width);
+                    window.extend(row[xs].iter().filter(|&&d| d != 0));
+                }
+                image.data[y * width + x] = match window.as_mut_slice() {
+                    full @ [_, _, _, _, _, _, _, _, _] => median9(full),
+                    window => {
+                        let mid = window.len() / 2;
+                        *window.select_nth_unstable(mid).1
+                    }
+                };
+            }
+        }
+    }
+}
+
+/// Branchless median of a full 3x3 window, which is most of them.
+fn median9(v: &mut [u16]) -> u16 {
+    // Paeth's exchange network
+    #[rustfmt::skip]
+    const PAIRS: [(usize, usize); 19] = [
+        (1, 2), (4, 5), (7, 8), (0, 1), (3, 4), (6, 7), (1, 2), (4, 5), (7, 8), (0, 3),
+        (5, 8), (4, 7), (3, 6), (1, 4), (2, 5), (4, 7), (4, 2), (6, 4), (4, 2),
+    ];
+    for (a, b) in PAIRS {
+        let (lo, hi) = (v[a].min(v[b]), v[a].max(v[b]));
+        v[a] = lo;
+        v[b] = hi;
+    }
+    v[4]
+}
+
+/// Edge preserving smoothing, weighting neighbours by distance and by depth difference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BilateralFilter {
+    pub radius: usize,
+    pub sigma_space: f32,
+    pub sigma_depth_mm: f32,
+}
+
+impl Default for BilateralFilter {
+    fn default() -> Self {
+        Self {
+            radius: 2,
+            sigma_space: 1.5,
+            sigma_depth_mm: 30.0,
+        }
+    }
+}
+
+impl DepthFilter for BilateralFilter {
+    fn apply(&mut self, image: &mut DepthImage) {
+        let (width, height) = (image.width, image.height);
+        let r = self.radius as isize;
+        let spatial: Vec<f32> = (-r..=r)
+            .flat_map(|dy| (-r..=r).map(move |dx| (dx * dx + dy * dy) as f32))
+            .map(|d2| (-d2 / (2.0 * self.sigma_space * self.sigma_space)).exp())
+            .collect();
+        // depth weights by absolute difference, anything further is ignored
+        let max_diff = (3.0 * self.sigma_depth_mm).ceil() as usize;
+        let range: Vec<f32> = (0..=max_diff)
+            .map(|d| (-((d * d) as f32) / (2.0 * self.sigma_depth_mm * self.sigma_depth_mm)).exp())
+            .collect();
+
+        let src = image.data.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let center = src[y * width + x];
+                if center == 0 {
+                    continue;
+                }
+                let (mut sum, mut weights) = (0.0, 0.0);
+                let side = 2 * self.radius + 1;
+                let x0 = x.saturating_sub(self.radius);
+                let x1 = (x + self.radius + 1).min(width);
+                for sy in y.saturating_sub(self.radius)..(y + self.radius + 1).min(height) {
+                    let row = &src[sy * width..(sy + 1) * width];
+                    let kernel = &spatial[(sy + self.radius - y) * side..];
+                    for sx in x0..x1 {
+                        let d = row[sx];
+                        let diff = d.abs_diff(center) as usize;
+                        if d == 0 || diff > max_diff {
+                            continue;
+                        }
+                        let w = kernel[sx + self.radius - x] * range[diff];
+                        sum += w * d as f32;
+                        weights += w;
+                    }
+                }
+                image.data[y * width + x] = (sum / weights).round() as u16;
+            }
+        }
+    }
+}
+
+/// Exponential smoothing over time. Pixels whose depth jumps by more than
+/// `reset_threshold_mm` are taken as moving and restart from the new value,
+/// so motion doesn't leave trails.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemporalFilter {
+    /// Weight of the newest frame, between 0 and 1.
+    pub alpha: f32,
+    pub reset_threshold_mm: u16,
+    state: Vec<f32>,
+}
+
+impl Default for TemporalFilter {
+    fn default() -> Self {
+        Self::new(0.4, 100)
+    }
+}
+
+impl TemporalFilter {
+    pub fn new(alpha: f32, reset_threshold_mm: u16) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            reset_threshold_mm,
+            state: Vec::new(),
+        }
+    }
+}
+
+impl DepthFilter for TemporalFilter {
+    fn apply(&mut self, image: &mut DepthImage) {
+        if self.state.len() != image.data.len() {
+            self.state = image.data.iter().map(|&d| d as f32).collect();
+            return;
+        }
+        for (d, s) in image.data.iter_mut().zip(&mut self.state) {
+            if *d == 0 {
+                continue;
+            }
+            let value = *d as f32;
+            if *s == 0.0 || (value - *s).abs() > self.reset_threshold_mm as f32 {
+                *s = value;
+            } else {
+                *s += self.alpha * (value - *s);
+            }
+            *d = s.round() as u16;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state.clear();
+    }
+}
+
+/// Fills holes with the depth of the nearest valid pixel, up to `max_distance`
+/// pixels away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HoleFilling {
+    pub max_distance: usize,
+}
+
+impl Default for HoleFilling {
+    fn default() -> Self {
+        Self { max_distance: 8 }
+    }
+}
+
+impl DepthFilter for HoleFilling {
+    fn apply(&mut self, image: &mut DepthImage) {
+        let (width, height) = (image.width, image.height);
+        let mut distance = vec![usize::MAX; image.data.len()];
+        let mut queue = VecDeque::new();
+        for (i, &d) in image.data.iter().enumerate() {
+            if d != 0 {
+                distance[i] = 0;
+                queue.push_back(i);
+            }
+        }
+
+        // breadth first from every valid pixel at once, so each hole is reached
+        // from its nearest one first
+        while let Some(i) = queue.pop_front() {
+            if distance[i] >= self.max_distance {
+                continue;
+            }
+            let (x, y) = (i % width, i / width);
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if distance[n] == usize::MAX {
+                    distance[n] = distance[i] + 1;
+                    image.data[n] = image.data[i];
+                    queue.push_back(n);
+                }
+            }
+        }
+    }
+}
+
+/// Removes small blobs of depth disconnected from their surroundings, which
+/// mostly come from reflections and sensor noise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeckleFilter {
+    /// Blobs with fewer pixels than this are removed.
+    pub max_size: usize,
+    /// Neighbouring pixels closer than this belong to the same blob.
+    pub max_diff_mm: u16,
+}
+
+impl Default for SpeckleFilter {
+    fn default() -> Self {
+        Self {
+            max_size: 50,
+            max_diff_mm: 40,
+        }
+    }
+}
+
+impl DepthFilter for SpeckleFilter {
+    fn apply(&mut self, image: &mut DepthImage) {
+        let (width, height) = (image.width, image.height);
+        let mut visited = vec![false; image.data.len()];
+        let mut blob = Vec::new();
+        let mut stack = Vec::new();
+        for start in 0..image.data.len() {
+            if visited[start] || image.data[start] == 0 {
+                continue;
+            }
+            visited[start] = true;
+            blob.clear();
+            stack.push(start);
+            while let Some(i) = stack.pop() {
+                blob.push(i);
+                let (x, y) = (i % width, i / width);
+                let neighbours = [
+                    (x > 0).then(|| i - 1),
+                    (x + 1 < width).then(|| i + 1),
+                    (y > 0).then(|| i - width),
+                    (y + 1 < height).then(|| i + width),
+                ];
+                for n in neighbours.into_iter().flatten() {
+                    let d = image.data[n];
+                    if !visited[n] && d != 0 && d.abs_diff(image.data[i]) <= self.max_diff_mm {
+                        visited[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+            if blob.len() < self.max_size {
+                for &i in &blob {
+                    image.data[i] = 0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, height: usize, data: &[u16]) -> DepthImage {
+        DepthImage::new(width, height, data.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn median9_matches_sorting() {
+        let mut seed = 12345u32;
+        for _ in 0..1000 {
+            let mut window: Vec<u16> = (0..9)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    (seed >> 16) as u16 % 50 + 1
+                })
+                .collect();
+            let mut sorted = window.clone();
+            sorted.sort_unstable();
+            assert_eq!(median9(&mut window), sorted[4]);
+        }
+    }
+
+    #[test]
+    fn median_removes_outliers() {
+        #[rustfmt::skip]
+        let mut depth = image(3, 3, &[
+            1000, 1001, 1002,
+            1003, 5000, 1004,
+            1005, 1006, 1007,
+        ]);
+        MedianFilter::default().apply(&mut depth);
+        assert_eq!(depth.get(1, 1), 1004);
+    }
+
+    #[test]
+    fn median_uses_partial_windows_at_edges() {
+        #[rustfmt::skip]
+        let mut depth = image(3, 3, &[
+            10, 20, 30,
+            40, 50, 60,
+            70, 80, 90,
+        ]);
+        MedianFilter::default().apply(&mut depth);
+        // upper median of 10, 20, 40 and 50
+        assert_eq!(depth.get(0, 0), 40);
+        assert_eq!(depth.get(2, 2), 80);
+        assert_eq!(depth.get(1, 0), 40);
+    }
+
+    #[test]
+    fn median_ignores_holes() {
+        #[rustfmt::skip]
+        let mut depth = image(3, 3, &[
+            0, 0, 0,
+            0, 700, 100,
+            0, 100, 100,
+        ]);
+        MedianFilter::default().apply(&mut depth);
+        assert_eq!(depth.get(1, 1), 100);
+        assert_eq!(depth.get(0, 0), 0);
+        assert_eq!(depth.hole_fraction(), 5.0 / 9.0);
+    }
+
+    #[test]
+    fn bilateral_smooths_noise() {
+        #[rustfmt::skip]
+        let mut depth = image(3, 3, &[
+            1000, 1000, 1000,
+            1000, 1020, 1000,
+            1000, 1000, 1000,
+        ]);
+        BilateralFilter::default().apply(&mut depth);
+        let center = depth.get(1, 1);
+        assert!((1000..1020).contains(&center), "{center}");
+        assert!(depth.data.iter().all(|&d| (1000..=1020).contains(&d)));
+    }
+
+    #[test]
+    fn bilateral_keeps_edges_and_holes() {
+        #[rustfmt::skip]
+        let data = [
+            1000, 1000, 2000, 2000,
+            1000, 0, 2000, 2000,
+            1000, 1000, 2000, 2000,
+        ];
+        let mut depth = image(4, 3, &data);
+        BilateralFilter::default().apply(&mut depth);
+        assert_eq!(depth.data, data);
+    }
+
+    #[test]
+    fn temporal_smooths_static_pixels() {
+        let mut filter = TemporalFilter::new(0.4, 100);
+        let mut depth = image(2, 1, &[1000, 0]);
+        filter.apply(&mut depth);
+        assert_eq!(depth.data, [1000, 0]);
+        for expected in [1004, 1006, 1008] {
+            let mut depth = image(2, 1, &[1010, 0]);
+            filter.apply(&mut depth);
+            assert_eq!(depth.data, [expected, 0]);
+        }
+    }
+
+    #[test]
+    fn temporal_restarts_moving_pixels() {
+        let mut filter = TemporalFilter::new(0.4, 100);
+        filter.apply(&mut image(1, 1, &[1000]));
+        let mut depth = image(1, 1, &[1500]);
+        filter.apply(&mut depth);
+        assert_eq!(depth.get(0, 0), 1500);
+        let mut depth = image(1, 1, &[1510]);
+        filter.apply(&mut depth);
+        assert_eq!(depth.get(0, 0), 1504);
+
+        filter.reset();
+        let mut depth = image(1, 1, &[1550]);
+        filter.apply(&mut depth);
+        assert_eq!(depth.get(0, 0), 1550);
+    }
+
+    #[test]
+    fn hole_filling_uses_the_nearest_pixel() {
+        #[rustfmt::skip]
+        let mut depth = image(5, 2, &[
+            1000, 0, 0, 0, 2000,
+            1000, 0, 0, 0, 0,
+        ]);
+        HoleFilling::default().apply(&mut depth);
+        assert_eq!(depth.get(1, 0), 1000);
+        assert_eq!(depth.get(3, 0), 2000);
+        assert_eq!(depth.get(4, 1), 2000);
+        assert_eq!(depth.get(1, 1), 1000);
+        assert_eq!(depth.hole_fraction(), 0.0);
+    }
+
+    #[test]
+    fn hole_filling_leaves_large_holes() {
+        let mut depth = image(6, 1, &[1000, 0, 0, 0, 0, 0]);
+        HoleFilling { max_distance: 2 }.apply(&mut depth);
+        assert_eq!(depth.data, [1000, 1000, 1000, 0, 0, 0]);
+    }
+
+    #[test]
+    fn speckle_removes_small_blobs() {
+        #[rustfmt::skip]
+        let mut depth = image(6, 4, &[
+            2000, 2010, 2020, 2030, 2040, 2050,
+            2000,  500,  510, 2030, 2040, 3000,
+            2000, 2010, 2020, 2030, 2040, 2050,
+               0,    0, 2020, 2030,    0,  900,
+        ]);
+        SpeckleFilter {
+            max_size: 5,
+            max_diff_mm: 40,
+        }
+        .apply(&mut depth);
+        #[rustfmt::skip]
+        let expected = [
+            2000, 2010, 2020, 2030, 2040, 2050,
+            2000,    0,    0, 2030, 2040,    0,
+            2000, 2010, 2020, 2030, 2040, 2050,
+               0,    0, 2020, 2030,    0,    0,
+        ];
+        assert_eq!(depth.data, expected);
+    }
+}
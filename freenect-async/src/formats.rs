@@ -89,6 +89,17 @@ pub enum FreenectDepthFormat {
     //DepthDummy = freenect_sys::freenect_depth_format_FREENECT_DEPTH_DUMMY
 }
 
+impl FreenectDepthFormat {
+    /// Value libfreenect reports for pixels without a depth reading.
+    pub fn invalid_depth(&self) -> u16 {
+        match self {
+            FreenectDepthFormat::Depth10Bit | FreenectDepthFormat::Depth10BitPacked => 1023,
+            FreenectDepthFormat::Depth11Bit | FreenectDepthFormat::Depth11BitPacked => 2047,
+            FreenectDepthFormat::DepthRegistered | FreenectDepthFormat::DepthMillimeters => 0,
+        }
+    }
+}
+
 impl fmt::Display for FreenectDepthFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -0,0 +1,102 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    marker::PhantomData,
+    task::Poll,
+    time::Duration,
+};
+
+use lending_stream::LendingStream;
+
+use crate::{
+    context::{FreenectContext, FreenectDeviceMode},
+    delay::Delay,
+    probe::SerialProbe,
+    FreenectError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotplugEvent {
+    DeviceArrived { serial: String },
+    DeviceRemoved { serial: String },
+}
+
+/// Detects devices being plugged in or removed by re-enumerating them
+/// periodically on a background thread. Devices already connected when the
+/// stream is created don't produce a [`HotplugEvent::DeviceArrived`] event.
+#[derive(Debug)]
+pub struct HotplugStream<'a, M: FreenectDeviceMode> {
+    // enumeration happens on the probe thread, but events are about this context's devices
+    context: PhantomData<&'a FreenectContext<M>>,
+    interval: Duration,
+    known: HashSet<String>,
+    events: VecDeque<HotplugEvent>,
+    delay: Delay,
+    probe: Option<SerialProbe>,
+}
+
+impl<'a, M: FreenectDeviceMode> HotplugStream<'a, M> {
+    pub(crate) fn new(
+        context: &'a FreenectContext<M>,
+        interval: Duration,
+    ) -> Result<Self, FreenectError> {
+        let known = context.device_attributes()?.into_iter().collect();
+        Ok(Self {
+            context: PhantomData,
+            interval,
+            known,
+            events: VecDeque::new(),
+            delay: Delay::new(interval),
+            probe: None,
+        })
+    }
+
+    fn rescan(&mut self, serials: Vec<String>) {
+        let current: HashSet<String> = serials.into_iter().collect();
+
+        for serial in self.known.difference(&current) {
+            self.events.push_back(HotplugEvent::DeviceRemoved {
+                serial: serial.clone(),
+            });
+        }
+        for serial in current.difference(&self.known) {
+            self.events.push_back(HotplugEvent::DeviceArrived {
+                serial: serial.clone(),
+            });
+        }
+
+        self.known = current;
+    }
+}
+
+impl<'a, M: FreenectDeviceMode> LendingStream for HotplugStream<'a, M> {
+    type Item<'c> = Result<HotplugEvent, FreenectError> where Self: 'c;
+
+    fn poll_next(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item<'_>>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            if let Some(probe) = &self.probe {
+                let Poll::Ready(serials) = probe.poll(cx) else {
+                    return Poll::Pending;
+                };
+                self.probe = None;
+                self.delay = Delay::new(self.interval);
+                match serials {
+                    Ok(serials) => self.rescan(serials),
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                }
+                continue;
+            }
+
+            if self.delay.poll_elapsed(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.probe = Some(SerialProbe::start());
+        }
+    }
+}
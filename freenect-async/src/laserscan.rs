@@ -0,0 +1,189 @@
+use std::ops::Range;
+
+use crate::{
+    calibration::Intrinsics, filters::DepthImage, plane::Plane, pointcloud::deproject,
+    FreenectError,
+};
+
+/// A planar scan in the layout of ROS' `sensor_msgs/LaserScan`: angles are
+/// counter-clockwise from the optical axis, so positive to the left, and beam
+/// `i` points at `angle_min + i * angle_increment`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaserScan {
+    pub angle_min: f32,
+    pub angle_max: f32,
+    pub angle_increment: f32,
+    pub range_min: f32,
+    pub range_max: f32,
+    /// Ranges in metres, infinite for beams without a return.
+    pub ranges: Vec<f32>,
+}
+
+impl LaserScan {
+    pub fn angles(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.ranges.len()).map(|i| self.angle_min + i as f32 * self.angle_increment)
+    }
+}
+
+/// Which part of the depth frame is flattened into the scan.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanBand {
+    /// Rows of the image.
+    Rows(Range<usize>),
+    /// Points between `min` and `max` metres above the optical axis, with the
+    /// sensor assumed level.
+    Height { min: f32, max: f32 },
+    /// Points between `min` and `max` metres above the floor, e.g. from a
+    /// [`FloorDetector`](crate::plane::FloorDetector), so a tilted sensor still
+    /// sees obstacles at a fixed height.
+    AboveFloor { floor: Plane, min: f32, max: f32 },
+}
+
+/// Emulates a laser scanner from a millimetre depth frame, keeping the nearest
+/// point of the band in each direction. Ranges are measured in the sensor's
+/// horizontal plane and those outside `range_min..=range_max` are dropped.
+pub fn depth_to_laserscan(
+    image: &DepthImage,
+    intrinsics: &Intrinsics,
+    band: &ScanBand,
+    range_min: f32,
+    range_max: f32,
+) -> Result<LaserScan, FreenectError> {
+    let (width, height) = (image.width, image.height);
+    if width != intrinsics.width as usize || height != intrinsics.height as usize || width < 2 {
+        return Err(FreenectError::BadVideoFormat);
+    }
+
+    // one beam per column, spanning the angles of the outermost columns
+    let angle_min = -((width - 1) as f64 - intrinsics.cx).atan2(intrinsics.fx) as f32;
+    let angle_max = intrinsics.cx.atan2(intrinsics.fx) as f32;
+    let angle_increment = (angle_max - angle_min) / (width - 1) as f32;
+    let mut ranges = vec![f32::INFINITY; width];
+
+    let rows = match band {
+        ScanBand::Rows(rows) => rows.start.min(height)..rows.end.min(height),
+        _ => 0..height,
+    };
+    for v in rows {
+        for u in 0..width {
+            let d = image.data[v * width + u];
+            if d == 0 {
+                continue;
+            }
+            let point = deproject(intrinsics, u, v, d);
+            let in_band = match band {
+                ScanBand::Rows(_) => true,
+                ScanBand::Height { min, max } => (*min..=*max).contains(&-point[1]),
+                ScanBand::AboveFloor { floor, min, max } => {
+                    let above = floor.distance(point.map(f64::from)) as f32;
+                    (*min..=*max).contains(&above)
+                }
+            };
+            if !in_band {
+                continue;
+            }
+
+            let range = point[0].hypot(point[2]);
+            if !(range_min..=range_max).contains(&range) {
+                continue;
+            }
+            let angle = (-point[0]).atan2(point[2]);
+            let beam = ((angle - angle_min) / angle_increment).round();
+            if beam < 0.0 || beam >= width as f32 {
+                continue;
+            }
+            let beam = &mut ranges[beam as usize];
+            *beam = beam.min(range);
+        }
+    }
+
+    Ok(LaserScan {
+        angle_min,
+        angle_max,
+        angle_increment,
+        range_min,
+        range_max,
+        ranges,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pointcloud::KINECT_DEPTH_INTRINSICS;
+
+    // a wall 2 m in front of the sensor, with a post 1 m away on the left
+    fn wall() -> DepthImage {
+        let (width, height) = (640, 480);
+        let data = (0..width * height)
+            .map(|i| {
+                if (100..120).contains(&(i % width)) {
+                    1000
+                } else {
+                    2000
+                }
+            })
+            .collect();
+        DepthImage::new(width, height, data).unwrap()
+    }
+
+    fn scan(band: ScanBand, range_max: f32) -> LaserScan {
+        depth_to_laserscan(&wall(), &KINECT_DEPTH_INTRINSICS, &band, 0.5, range_max).unwrap()
+    }
+
+    #[test]
+    fn measures_a_wall() {
+        let scan = scan(ScanBand::Rows(230..250), 5.0);
+        assert_eq!(scan.ranges.len(), 640);
+        let angles: Vec<f32> = scan.angles().collect();
+        assert!((angles[639] - scan.angle_max).abs() < 1e-5);
+
+        let ahead = (-scan.angle_min / scan.angle_increment).round() as usize;
+        assert!(
+            (scan.ranges[ahead] - 2.0).abs() < 1e-3,
+            "{}",
+            scan.ranges[ahead]
+        );
+        for (range, angle) in scan.ranges.iter().zip(&angles) {
+            if range.is_finite() && *range > 1.5 {
+                // the wall is flat, so its distance along the optical axis is constant
+                assert!((range * angle.cos() - 2.0).abs() < 0.02, "{range} {angle}");
+            }
+        }
+    }
+
+    #[test]
+    fn left_is_positive() {
+        let scan = scan(ScanBand::Rows(230..250), 5.0);
+        let post: Vec<f32> = scan
+            .angles()
+            .zip(&scan.ranges)
+            .filter(|(_, &range)| range < 1.5)
+            .map(|(angle, _)| angle)
+            .collect();
+        assert!(!post.is_empty());
+        assert!(post.iter().all(|&angle| angle > 0.3));
+    }
+
+    #[test]
+    fn drops_points_outside_the_band_and_range() {
+        let scan_above = scan(ScanBand::Height { min: 5.0, max: 6.0 }, 5.0);
+        assert!(scan_above.ranges.iter().all(|r| r.is_infinite()));
+        let short = scan(ScanBand::Rows(230..250), 1.9);
+        assert!(short.ranges.iter().all(|r| r.is_infinite() || *r < 1.5));
+    }
+
+    #[test]
+    fn rejects_mismatched_intrinsics() {
+        let image = DepthImage::new(320, 240, vec![1000; 320 * 240]).unwrap();
+        let result = depth_to_laserscan(
+            &image,
+            &KINECT_DEPTH_INTRINSICS,
+            &ScanBand::Rows(0..240),
+            0.5,
+            5.0,
+        );
+        assert!(matches!(result, Err(FreenectError::BadVideoFormat)));
+    }
+}
@@ -1,38 +1,268 @@
+pub mod aggregator;
+pub mod alternating;
+pub mod background;
+pub mod calibration;
 pub mod context;
+mod delay;
 pub mod device;
+pub mod filters;
 pub mod formats;
+pub mod hotplug;
+pub mod laserscan;
+#[cfg(any(feature = "log", feature = "tracing"))]
+mod logging;
 pub mod motors_led;
+pub mod occupancy;
+pub mod plane;
+pub mod pointcloud;
+mod probe;
+pub mod shared;
+pub mod stats;
 pub mod stream;
+pub mod supervisor;
+pub mod undistort;
 pub mod video;
 
+use context::Subdevices;
+use video::CameraFlags;
 use thiserror::Error;
 
-#[derive(Debug, Clone, Copy, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum FreenectError {
-    #[error("Unable to create the freenect context.")]
-    ContextCreationError,
-    #[error("Unable to list connected freenect devices.")]
-    DeviceListError,
+    #[error("Unable to create the freenect context: {0}")]
+    ContextCreationError(#[source] UsbError),
+    #[error("Unable to list connected freenect devices: {0}")]
+    DeviceListError(#[source] UsbError),
     #[error("Device {0} not found.")]
     DeviceNotFound(u32),
-    #[error("Unable to open device {0}.")]
-    OpenDeviceError(u32),
-    #[error("Unable to set LED state.")]
-    LedStateError,
+    #[error("Unable to open device {0}: {1}")]
+    OpenDeviceError(u32, #[source] UsbError),
+    #[error("Device with serial {0} not found.")]
+    DeviceNotFoundBySerial(String),
+    #[error("Unable to open device with serial {0}: {1}")]
+    OpenDeviceBySerialError(String, #[source] UsbError),
+    #[error("Unable to set LED state: {0}")]
+    LedStateError(#[source] UsbError),
     #[error("A tilt angle of {0}° is out of range! It should be between ±31°.")]
     TiltAngleOutOfRange(f64),
-    #[error("Unable to set tilt angle.")]
-    TiltAngleError,
+    #[error("Unable to set tilt angle: {0}")]
+    TiltAngleError(#[source] UsbError),
     #[error("A brightness value of {0} is out of range! It should be between 1 and 50.")]
     BrightnessOutOfRange(u16),
-    #[error("Unable to set brightness value.")]
-    SetBrightnessError,
-    #[error("Unable to get brightness value.")]
-    GetBrightnessError,
-    #[error("Error while processing events")]
-    EventProcessingError,
+    #[error("Unable to set brightness value: {0}")]
+    SetBrightnessError(#[source] UsbError),
+    #[error("Unable to get brightness value: {0}")]
+    GetBrightnessError(#[source] UsbError),
+    #[error("Unable to set camera flag {0:?}: {1}")]
+    SetFlagError(CameraFlags, #[source] UsbError),
+    #[error("Unable to get exposure: {0}")]
+    GetExposureError(#[source] UsbError),
+    #[error("Unable to set exposure: {0}")]
+    SetExposureError(#[source] UsbError),
+    #[error("Unable to identify the Kinect model.")]
+    UnknownModel,
+    #[error("{0} is not supported by this Kinect model.")]
+    Unsupported(&'static str),
+    #[error("Error while processing events: {0}")]
+    EventProcessingError(#[source] UsbError),
+    #[error("The device was disconnected.")]
+    DeviceDisconnected,
+    #[error("The device has no serial number to identify it by.")]
+    UnknownSerial,
     #[error("Error with the video stream.")]
     VideoStreamError,
+    #[error("Unable to start the stream: {0}")]
+    StreamStartError(#[source] UsbError),
     #[error("Bad video format")]
     BadVideoFormat,
+    #[error("Subdevice {0:?} was not selected for this context.")]
+    SubdeviceNotSelected(Subdevices),
+    #[error("Subdevice {0:?} could not be opened.")]
+    SubdeviceNotOpened(Subdevices),
+    #[error(transparent)]
+    Algorithm(#[from] AlgorithmError),
+}
+
+impl FreenectError {
+    /// The USB error reported by libfreenect, if this error came from a failed call.
+    pub fn usb_error(&self) -> Option<UsbError> {
+        match self {
+            FreenectError::ContextCreationError(e)
+            | FreenectError::DeviceListError(e)
+            | FreenectError::OpenDeviceError(_, e)
+            | FreenectError::OpenDeviceBySerialError(_, e)
+            | FreenectError::LedStateError(e)
+            | FreenectError::TiltAngleError(e)
+            | FreenectError::SetBrightnessError(e)
+            | FreenectError::GetBrightnessError(e)
+            | FreenectError::SetFlagError(_, e)
+            | FreenectError::GetExposureError(e)
+            | FreenectError::SetExposureError(e)
+            | FreenectError::EventProcessingError(e)
+            | FreenectError::StreamStartError(e) => Some(*e),
+            _ => None,
+        }
+    }
+
+    /// The raw negative value returned by libfreenect.
+    pub fn code(&self) -> Option<i32> {
+        self.usb_error().map(|e| e.code())
+    }
+
+    pub fn is_permission_denied(&self) -> bool {
+        self.usb_error() == Some(UsbError::Access)
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.usb_error() == Some(UsbError::Busy)
+    }
+
+    pub fn is_no_device(&self) -> bool {
+        matches!(self, FreenectError::DeviceDisconnected)
+            || self.usb_error() == Some(UsbError::NoDevice)
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.usb_error() == Some(UsbError::Timeout)
+    }
+}
+
+/// Failures of the processing modules, which never come from libfreenect.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AlgorithmError {
+    #[error("At least 3 views of the calibration target are needed, got {0}.")]
+    NotEnoughCalibrationViews(usize),
+    #[error("Expected {expected} checkerboard corners, got {got}.")]
+    InvalidCornerCount { expected: usize, got: usize },
+    #[error("Unable to calibrate the camera from the given views.")]
+    CalibrationFailed,
+    #[error("No plane with enough inliers was found.")]
+    PlaneNotFound,
+    #[error("Device {0} is out of range for an aggregator of {1} devices.")]
+    AggregatorDeviceOutOfRange(usize, usize),
+}
+
+/// Error codes returned by libfreenect, which passes through the ones from libusb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum UsbError {
+    #[error("Invalid parameter.")]
+    InvalidParam,
+    #[error("Permission denied, make sure the udev rules for the Kinect are installed.")]
+    Access,
+    #[error("No such device, it may have been disconnected.")]
+    NoDevice,
+    #[error("Entity not found.")]
+    NotFound,
+    #[error("Resource busy, the device may be claimed by another process.")]
+    Busy,
+    #[error("Operation timed out.")]
+    Timeout,
+    #[error("Overflow.")]
+    Overflow,
+    #[error("Pipe error.")]
+    Pipe,
+    #[error("System call interrupted.")]
+    Interrupted,
+    #[error("Insufficient memory.")]
+    NoMem,
+    #[error("Operation not supported.")]
+    NotSupported,
+    // libfreenect also uses -1 for its own failures, so LIBUSB_ERROR_IO ends up here
+    #[error("libfreenect returned error code {0}.")]
+    Other(i32),
+}
+
+impl UsbError {
+    pub fn code(&self) -> i32 {
+        match self {
+            UsbError::InvalidParam => -2,
+            UsbError::Access => -3,
+            UsbError::NoDevice => -4,
+            UsbError::NotFound => -5,
+            UsbError::Busy => -6,
+            UsbError::Timeout => -7,
+            UsbError::Overflow => -8,
+            UsbError::Pipe => -9,
+            UsbError::Interrupted => -10,
+            UsbError::NoMem => -11,
+            UsbError::NotSupported => -12,
+            UsbError::Other(code) => *code,
+        }
+    }
+}
+
+impl From<i32> for UsbError {
+    fn from(code: i32) -> Self {
+        match code {
+            -2 => UsbError::InvalidParam,
+            -3 => UsbError::Access,
+            -4 => UsbError::NoDevice,
+            -5 => UsbError::NotFound,
+            -6 => UsbError::Busy,
+            -7 => UsbError::Timeout,
+            -8 => UsbError::Overflow,
+            -9 => UsbError::Pipe,
+            -10 => UsbError::Interrupted,
+            -11 => UsbError::NoMem,
+            -12 => UsbError::NotSupported,
+            code => UsbError::Other(code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usb_error_codes_round_trip() {
+        let errors = [
+            UsbError::InvalidParam,
+            UsbError::Access,
+            UsbError::NoDevice,
+            UsbError::NotFound,
+            UsbError::Busy,
+            UsbError::Timeout,
+            UsbError::Overflow,
+            UsbError::Pipe,
+            UsbError::Interrupted,
+            UsbError::NoMem,
+            UsbError::NotSupported,
+        ];
+        for (error, code) in errors.into_iter().zip((-12..=-2).rev()) {
+            assert_eq!(UsbError::from(code), error);
+            assert_eq!(error.code(), code);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_are_kept() {
+        for code in [-1, -13, -99, 0] {
+            assert_eq!(UsbError::from(code), UsbError::Other(code));
+            assert_eq!(UsbError::from(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn open_errors_include_the_cause() {
+        let error = FreenectError::OpenDeviceError(1, UsbError::Busy);
+        assert_eq!(
+            error.to_string(),
+            "Unable to open device 1: Resource busy, the device may be claimed by another process."
+        );
+        assert!(error.is_busy());
+        let error = FreenectError::OpenDeviceBySerialError("A00".into(), UsbError::Access);
+        assert!(error.to_string().contains("A00: Permission denied"));
+    }
+
+    #[test]
+    fn wrapped_errors_include_the_cause() {
+        let error = FreenectError::SetBrightnessError(UsbError::NoDevice);
+        assert_eq!(
+            error.to_string(),
+            "Unable to set brightness value: No such device, it may have been disconnected."
+        );
+        let error = FreenectError::EventProcessingError(UsbError::Timeout);
+        assert!(error.to_string().ends_with(": Operation timed out."));
+    }
 }
@@ -0,0 +1,42 @@
+use crate::context::{FreenectContext, FreenectDeviceMode, FreenectLogLevel};
+
+pub(crate) const TARGET: &str = "freenect";
+
+impl<M: FreenectDeviceMode> FreenectContext<M> {
+    /// Forwards libfreenect messages to the `log` facade under the `freenect` target.
+    #[cfg(feature = "log")]
+    pub fn forward_to_log(&self) {
+        self.set_log_callback(Some(Box::new(|level, msg| {
+            log::log!(target: TARGET, to_log_level(level), "{}", msg)
+        })));
+    }
+
+    /// Forwards libfreenect messages to `tracing` events under the `freenect` target.
+    #[cfg(feature = "tracing")]
+    pub fn forward_to_tracing(&self) {
+        self.set_log_callback(Some(Box::new(|level, msg| match level {
+            FreenectLogLevel::Fatal | FreenectLogLevel::Error => {
+                tracing::error!(target: TARGET, "{}", msg)
+            }
+            FreenectLogLevel::Warning => tracing::warn!(target: TARGET, "{}", msg),
+            FreenectLogLevel::Notice | FreenectLogLevel::Info => {
+                tracing::info!(target: TARGET, "{}", msg)
+            }
+            FreenectLogLevel::Debug => tracing::debug!(target: TARGET, "{}", msg),
+            FreenectLogLevel::Spew | FreenectLogLevel::Flood => {
+                tracing::trace!(target: TARGET, "{}", msg)
+            }
+        })));
+    }
+}
+
+#[cfg(feature = "log")]
+fn to_log_level(level: FreenectLogLevel) -> log::Level {
+    match level {
+        FreenectLogLevel::Fatal | FreenectLogLevel::Error => log::Level::Error,
+        FreenectLogLevel::Warning => log::Level::Warn,
+        FreenectLogLevel::Notice | FreenectLogLevel::Info => log::Level::Info,
+        FreenectLogLevel::Debug => log::Level::Debug,
+        FreenectLogLevel::Spew | FreenectLogLevel::Flood => log::Level::Trace,
+    }
+}
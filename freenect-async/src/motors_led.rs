@@ -1,7 +1,7 @@
 use crate::{
     context::{
-        FreenectDeviceMode, FreenectDeviceReady, FreenectReadyAll, FreenectReadyMotors,
-        FreenectReadyVideoMotors,
+        FreenectDeviceMode, FreenectDeviceReady, FreenectReadyAll, FreenectReadyDynamic,
+        FreenectReadyMotors, FreenectReadyVideoMotors, Subdevices,
     },
     device::FreenectDevice,
     FreenectError,
@@ -18,14 +18,18 @@ impl FreenectMotors for FreenectReadyMotors {}
 
 impl FreenectMotors for FreenectReadyAll {}
 
+impl FreenectMotors for FreenectReadyDynamic {}
+
 impl<'a, D> FreenectDevice<'a, D>
 where
     D: FreenectMotors,
 {
     pub fn set_led(&self, state: FreenectLedState) -> Result<(), FreenectError> {
+        self.require(Subdevices::MOTOR)?;
         unsafe {
-            if freenect_sys::freenect_set_led(self.inner, state as u32) < 0 {
-                return Err(FreenectError::LedStateError);
+            let res = freenect_sys::freenect_set_led(self.inner, state as u32);
+            if res < 0 {
+                return Err(FreenectError::LedStateError(res.into()));
             }
         }
 
@@ -36,19 +40,23 @@ where
         if !(MIN_TILT_ANGLE..=MAX_TILT_ANGLE).contains(&deg) {
             return Err(FreenectError::TiltAngleOutOfRange(deg));
         }
+        self.require(Subdevices::MOTOR)?;
         unsafe {
-            if freenect_sys::freenect_set_tilt_degs(self.inner, deg) < 0 {
-                return Err(FreenectError::TiltAngleError);
+            let res = freenect_sys::freenect_set_tilt_degs(self.inner, deg);
+            if res < 0 {
+                return Err(FreenectError::TiltAngleError(res.into()));
             }
         }
         Ok(())
     }
 
     pub fn get_tilt_degree(&self) -> Result<f64, FreenectError> {
+        self.require(Subdevices::MOTOR)?;
         todo!()
     }
 
     pub fn get_tilt_state(&self) -> Result<FreenectTiltState, FreenectError> {
+        self.require(Subdevices::MOTOR)?;
         Ok(FreenectTiltState)
     }
 }
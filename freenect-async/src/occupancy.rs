@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::pointcloud::PointCloud;
+
+// log-odds updates and clamping, the usual values from OctoMap
+const LOG_ODDS_HIT: f32 = 0.85;
+const LOG_ODDS_MISS: f32 = -0.4;
+const LOG_ODDS_MIN: f32 = -2.0;
+const LOG_ODDS_MAX: f32 = 3.5;
+
+type VoxelKey = [i32; 3];
+
+/// Position and orientation of the depth camera in the map.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    /// Rotation from the camera frame to the map frame.
+    pub rotation: [[f64; 3]; 3],
+    /// Position of the camera in the map, in metres.
+    pub translation: [f64; 3],
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self {
+            rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            translation: [0.0; 3],
+        }
+    }
+}
+
+impl Pose {
+    pub fn new(rotation: [[f64; 3]; 3], translation: [f64; 3]) -> Self {
+        Self {
+            rotation,
+            translation,
+        }
+    }
+
+    /// A camera at `position` tilted up by `tilt_deg`, e.g. from the motor's
+    /// tilt angle. The map frame is that of the level camera: x right, y down
+    /// and z forward.
+    pub fn from_tilt(tilt_deg: f64, position: [f64; 3]) -> Self {
+        let (sin, cos) = tilt_deg.to_radians().sin_cos();
+        Self::new(
+            [[1.0, 0.0, 0.0], [0.0, cos, -sin], [0.0, sin, cos]],
+            position,
+        )
+    }
+
+    pub fn transform(&self, point: [f64; 3]) -> [f64; 3] {
+        let r = &self.rotation;
+        std::array::from_fn(|i| {
+            r[i][0] * point[0] + r[i][1] * point[1] + r[i][2] * point[2] + self.translation[i]
+        })
+    }
+}
+
+/// Sparse 3D occupancy map with a log-odds estimate per voxel.
+///
+/// Every inserted point marks its voxel as more likely occupied and the voxels
+/// on the ray from the camera to it as more likely free, so obstacles that
+/// move away are cleared again.
+#[derive(Debug, Clone)]
+pub struct OccupancyGrid {
+    voxel_size: f64,
+    voxels: HashMap<VoxelKey, f32>,
+    /// Points further than this only clear space up to this range, in metres.
+    pub max_range: f64,
+    /// Only every `point_step`th point of a cloud is inserted.
+    pub point_step: usize,
+}
+
+impl OccupancyGrid {
+    pub fn new(voxel_size: f64) -> Self {
+        Self {
+            voxel_size,
+            voxels: HashMap::new(),
+            max_range: 4.0,
+            point_step: 4,
+        }
+    }
+
+    pub fn voxel_size(&self) -> f64 {
+        self.voxel_size
+    }
+
+    /// Number of voxels observed so far, free or occupied.
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.voxels.clear();
+    }
+
+    /// Integrates a cloud seen by the camera at `pose`.
+    pub fn insert(&mut self, cloud: &PointCloud, pose: &Pose) {
+        let origin = pose.translation;
+        let mut free = HashSet::new();
+        let mut occupied = HashSet::new();
+
+        for point in cloud.valid_points().step_by(self.point_step.max(1)) {
+            let camera = point.map(f64::from);
+            let range =
+                (camera[0] * camera[0] + camera[1] * camera[1] + camera[2] * camera[2]).sqrt();
+            let (end, hit) = if range > self.max_range {
+                (camera.map(|x| x * self.max_range / range), false)
+            } else {
+                (camera, true)
+            };
+            let end = pose.transform(end);
+            self.cast_ray(origin, end, |key| {
+                free.insert(key);
+            });
+            if hit {
+                occupied.insert(self.key(end));
+            }
+        }
+
+        // a voxel hit by one ray and crossed by another is occupied
+        for key in free.difference(&occupied) {
+            self.update(*key, LOG_ODDS_MISS);
+        }
+        for key in occupied {
+            self.update(key, LOG_ODDS_HIT);
+        }
+    }
+
+    /// Probability that the voxel containing `point` is occupied, `None` if it
+    /// was never observed.
+    pub fn occupancy(&self, point: [f64; 3]) -> Option<f32> {
+        self.voxels.get(&self.key(point)).map(|&l| probability(l))
+    }
+
+    pub fn is_occupied(&self, point: [f64; 3]) -> bool {
+        self.voxels.get(&self.key(point)).is_some_and(|&l| l > 0.0)
+    }
+
+    /// Centres of the voxels more likely occupied than free.
+    pub fn occupied_voxels(&self) -> impl Iterator<Item = [f64; 3]> + '_ {
+        self.voxels
+            .iter()
+            .filter(|(_, &l)| l > 0.0)
+            .map(|(key, _)| self.center(*key, self.voxel_size))
+    }
+
+    /// Centres of occupied voxels of `voxel_size`, coarser than the grid's. A
+    /// coarse voxel is occupied if any voxel inside it is, so thin obstacles
+    /// survive the downsampling.
+    pub fn export(&self, voxel_size: f64) -> Vec<[f64; 3]> {
+        let factor = (voxel_size / self.voxel_size).round().max(1.0) as i32;
+        let coarse: HashSet<VoxelKey> = self
+            .voxels
+            .iter()
+            .filter(|(_, &l)| l > 0.0)
+            .map(|(key, _)| key.map(|k| k.div_euclid(factor)))
+            .collect();
+        let size = self.voxel_size * factor as f64;
+        coarse
+            .into_iter()
+            .map(|key| self.center(key, size))
+            .collect()
+    }
+
+    fn update(&mut self, key: VoxelKey, log_odds: f32) {
+        let value = self.voxels.entry(key).or_insert(0.0);
+        *value = (*value + log_odds).clamp(LOG_ODDS_MIN, LOG_ODDS_MAX);
+    }
+
+    fn key(&self, point: [f64; 3]) -> VoxelKey {
+        point.map(|x| (x / self.voxel_size).floor() as i32)
+    }
+
+    fn center(&self, key: VoxelKey, size: f64) -> [f64; 3] {
+        key.map(|k| (k as f64 + 0.5) * size)
+    }
+
+    /// Calls `visit` with every voxel the segment passes through, except the
+    /// one containing `end` (Amanatides and Woo).
+    fn cast_ray(&self, start: [f64; 3], end: [f64; 3], mut visit: impl FnMut(VoxelKey)) {
+        let mut key = self.key(start);
+        let end_key = self.key(end);
+        let direction: [f64; 3] = std::array::from_fn(|i| end[i] - start[i]);
+        let mut step = [0; 3];
+        let mut t_max = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+        for i in 0..3 {
+            if direction[i] == 0.0 {
+                continue;
+            }
+            step[i] = direction[i].signum() as i32;
+            let boundary = (key[i] + (step[i] > 0) as i32) as f64 * self.voxel_size;
+            t_max[i] = (boundary - start[i]) / direction[i];
+            t_delta[i] = self.voxel_size / direction[i].abs();
+        }
+
+        while key != end_key {
+            visit(key);
+            let axis = (0..3)
+                .min_by(|&a, &b| t_max[a].total_cmp(&t_max[b]))
+                .unwrap();
+            if t_max[axis] > 1.0 {
+                break;
+            }
+            key[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+        }
+    }
+}
+
+fn probability(log_odds: f32) -> f32 {
+    1.0 - 1.0 / (1.0 + log_odds.exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calibration::Intrinsics, filters::DepthImage};
+
+    // a tenth of the Kinect's resolution keeps the tests quick
+    const INTRINSICS: Intrinsics = Intrinsics {
+        width: 64,
+        height: 48,
+        fx: 59.4,
+        fy: 59.1,
+        cx: 32.0,
+        cy: 24.0,
+    };
+
+    fn wall(depth_mm: u16) -> PointCloud {
+        let image = DepthImage::new(64, 48, vec![depth_mm; 64 * 48]).unwrap();
+        PointCloud::from_depth(&image, &INTRINSICS).unwrap()
+    }
+
+    fn single_point(point: [f32; 3]) -> PointCloud {
+        PointCloud {
+            width: 1,
+            height: 1,
+            points: vec![Some(point)],
+        }
+    }
+
+    #[test]
+    fn one_ray_clears_space_up_to_the_hit() {
+        let mut grid = OccupancyGrid::new(0.1);
+        grid.insert(&single_point([0.0, 0.0, 1.05]), &Pose::default());
+        assert!(grid.is_occupied([0.0, 0.0, 1.05]));
+        assert_eq!(
+            grid.occupancy([0.0, 0.0, 1.05]),
+            Some(probability(LOG_ODDS_HIT))
+        );
+        for z in 0..10 {
+            let point = [0.0, 0.0, z as f64 / 10.0 + 0.05];
+            assert_eq!(grid.occupancy(point), Some(probability(LOG_ODDS_MISS)));
+        }
+        assert_eq!(grid.occupancy([0.0, 0.0, 1.15]), None);
+        assert_eq!(grid.len(), 11);
+    }
+
+    #[test]
+    fn log_odds_are_clamped() {
+        let mut grid = OccupancyGrid::new(0.1);
+        let cloud = single_point([0.0, 0.0, 0.55]);
+        for _ in 0..20 {
+            grid.insert(&cloud, &Pose::default());
+        }
+        assert_eq!(
+            grid.occupancy([0.0, 0.0, 0.55]),
+            Some(probability(LOG_ODDS_MAX))
+        );
+        assert_eq!(
+            grid.occupancy([0.0, 0.0, 0.05]),
+            Some(probability(LOG_ODDS_MIN))
+        );
+
+        // clamping keeps the voxels quick to change their mind
+        for _ in 0..9 {
+            grid.insert(&single_point([0.0, 0.0, 0.95]), &Pose::default());
+        }
+        assert!(!grid.is_occupied([0.0, 0.0, 0.55]));
+    }
+
+    #[test]
+    fn moved_walls_are_cleared() {
+        let mut grid = OccupancyGrid::new(0.05);
+        grid.point_step = 1;
+        grid.insert(&wall(2000), &Pose::default());
+        assert!(grid.is_occupied([0.0, 0.0, 2.01]));
+        assert!(!grid.is_occupied([0.0, 0.0, 1.0]));
+        assert!(grid.occupancy([0.0, 0.0, 3.0]).is_none());
+
+        for _ in 0..5 {
+            grid.insert(&wall(3000), &Pose::default());
+        }
+        assert!(!grid.is_occupied([0.0, 0.0, 2.01]));
+        assert!(grid.is_occupied([0.0, 0.0, 3.01]));
+        let coarse = grid.export(0.2).len();
+        assert!(coarse > 0 && coarse < grid.occupied_voxels().count());
+    }
+
+    #[test]
+    fn points_beyond_max_range_only_clear() {
+        let mut grid = OccupancyGrid::new(0.1);
+        grid.max_range = 1.0;
+        grid.insert(&wall(2000), &Pose::from_tilt(0.0, [1.0, 0.0, 0.0]));
+        assert_eq!(grid.occupied_voxels().count(), 0);
+        assert!(grid.occupancy([1.0, 0.0, 0.5]).is_some());
+    }
+
+    #[test]
+    fn tilting_up_points_forward_up() {
+        let forward = Pose::from_tilt(90.0, [0.0; 3]).transform([0.0, 0.0, 1.0]);
+        assert!((forward[1] + 1.0).abs() < 1e-9, "{forward:?}");
+    }
+}
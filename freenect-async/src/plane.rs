@@ -0,0 +1,323 @@
+use crate::{
+    calibration::{cross, smallest_eigenvector},
+    pointcloud::PointCloud,
+    AlgorithmError, FreenectError,
+};
+
+/// The plane `normal · p + offset = 0`, with a unit normal.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: [f64; 3],
+    pub offset: f64,
+}
+
+impl Plane {
+    /// Plane through three points, `None` if they are (nearly) collinear.
+    pub fn from_points(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> Option<Self> {
+        let n = cross(sub(b, a), sub(c, a));
+        let length = dot(n, n).sqrt();
+        if length < 1e-9 {
+            return None;
+        }
+        let normal = n.map(|x| x / length);
+        Some(Self {
+            normal,
+            offset: -dot(normal, a),
+        })
+    }
+
+    /// The coefficients `[a, b, c, d]` of `ax + by + cz + d = 0`.
+    pub fn coefficients(&self) -> [f64; 4] {
+        let [a, b, c] = self.normal;
+        [a, b, c, self.offset]
+    }
+
+    /// Signed distance of a point, positive on the side the normal points to.
+    pub fn distance(&self, point: [f64; 3]) -> f64 {
+        dot(self.normal, point) + self.offset
+    }
+
+    // flips the plane so that the origin, i.e. the sensor, is on the positive side
+    fn facing_origin(self) -> Self {
+        if self.offset < 0.0 {
+            Self {
+                normal: self.normal.map(|x| -x),
+                offset: -self.offset,
+            }
+        } else {
+            self
+        }
+    }
+}
+
+/// The floor found by [`FloorDetector`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloorPlane {
+    /// The floor, with its normal pointing up towards the sensor.
+    pub plane: Plane,
+    /// Height of the sensor above the floor, in metres.
+    pub height: f64,
+    /// Angle of the optical axis below the horizon in radians, negative when
+    /// looking up.
+    pub pitch: f64,
+    /// Rotation around the optical axis in radians, positive when the sensor
+    /// leans to the right.
+    pub roll: f64,
+    /// Which points of the cloud lie on the floor, in the cloud's layout.
+    pub inliers: Vec<bool>,
+    pub inlier_count: usize,
+}
+
+/// Finds the dominant plane in a [`PointCloud`] with RANSAC, then refines it
+/// with a least squares fit to its inliers.
+///
+/// Without a gravity vector the largest plane facing the sensor wins, which is
+/// the floor in most scenes but can be a wall when close to one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloorDetector {
+    pub iterations: usize,
+    /// Maximum distance of an inlier from the plane, in metres.
+    pub inlier_threshold: f64,
+    /// Fraction of the valid points that must lie on the plane.
+    pub min_inlier_fraction: f64,
+    /// Only every `sample_step`th point is used while searching, to save time.
+    pub sample_step: usize,
+    gravity: Option<[f64; 3]>,
+    max_angle: f64,
+    seed: u64,
+}
+
+impl Default for FloorDetector {
+    fn default() -> Self {
+        Self {
+            iterations: 200,
+            inlier_threshold: 0.02,
+            min_inlier_fraction: 0.1,
+            sample_step: 4,
+            gravity: None,
+            max_angle: 20f64.to_radians(),
+            seed: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+}
+
+impl FloorDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only accepts planes whose normal is within `max_angle_deg` of up, as
+    /// given by gravity in the depth camera's frame (x right, y down, z
+    /// forward), e.g. from the accelerometer. A level sensor has a gravity of
+    /// `[0, 1, 0]`.
+    pub fn with_gravity(mut self, gravity: [f64; 3], max_angle_deg: f64) -> Self {
+        let length = dot(gravity, gravity).sqrt();
+        self.gravity = (length > 0.0).then(|| gravity.map(|x| x / length));
+        self.max_angle = max_angle_deg.to_radians();
+        self
+    }
+
+    /// Seed of the random sampling, for reproducible results.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed.max(1);
+        self
+    }
+
+    pub fn detect(&self, cloud: &PointCloud) -> Result<FloorPlane, FreenectError> {
+        let samples: Vec<[f64; 3]> = cloud
+            .valid_points()
+            .step_by(self.sample_step.max(1))
+            .map(|p| p.map(f64::from))
+            .collect();
+        if samples.len() < 3 {
+            return Err(FreenectError::Algorithm(AlgorithmError::PlaneNotFound));
+        }
+
+        let mut rng = self.seed;
+        let mut best: Option<(Plane, usize)> = None;
+        for _ in 0..self.iterations {
+            let mut pick = || samples[(xorshift(&mut rng) % samples.len() as u64) as usize];
+            let Some(plane) = Plane::from_points(pick(), pick(), pick()) else {
+                continue;
+            };
+            let plane = plane.facing_origin();
+            if !self.accepts(&plane) {
+                continue;
+            }
+            let count = self.count_inliers(&plane, &samples);
+            if !matches!(best, Some((_, c)) if c >= count) {
+                best = Some((plane, count));
+            }
+        }
+        let (plane, count) = best.ok_or(AlgorithmError::PlaneNotFound)?;
+        if (count as f64) < self.min_inlier_fraction * samples.len() as f64 {
+            return Err(FreenectError::Algorithm(AlgorithmError::PlaneNotFound));
+        }
+
+        let inliers: Vec<[f64; 3]> = samples
+            .iter()
+            .filter(|p| plane.distance(**p).abs() < self.inlier_threshold)
+            .copied()
+            .collect();
+        let plane = fit_plane(&inliers)
+            .map(Plane::facing_origin)
+            .filter(|p| self.accepts(p))
+            .unwrap_or(plane);
+
+        let mask: Vec<bool> = cloud
+            .points
+            .iter()
+            .map(|p| {
+                p.is_some_and(|p| plane.distance(p.map(f64::from)).abs() < self.inlier_threshold)
+            })
+            .collect();
+        let [nx, ny, nz] = plane.normal;
+        Ok(FloorPlane {
+            plane,
+            height: plane.offset,
+            pitch: (-nz).clamp(-1.0, 1.0).asin(),
+            roll: nx.atan2(-ny),
+            inlier_count: mask.iter().filter(|&&m| m).count(),
+            inliers: mask,
+        })
+    }
+
+    fn accepts(&self, plane: &Plane) -> bool {
+        match self.gravity {
+            // the normal points up, against gravity
+            Some(gravity) => -dot(plane.normal, gravity) >= self.max_angle.cos(),
+            None => true,
+        }
+    }
+
+    fn count_inliers(&self, plane: &Plane, points: &[[f64; 3]]) -> usize {
+        points
+            .iter()
+            .filter(|p| plane.distance(**p).abs() < self.inlier_threshold)
+            .count()
+    }
+}
+
+/// Least squares plane, normal to the direction of least variance.
+fn fit_plane(points: &[[f64; 3]]) -> Option<Plane> {
+    if points.len() < 3 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mut centroid = [0.0; 3];
+    for p in points {
+        for i in 0..3 {
+            centroid[i] += p[i] / n;
+        }
+    }
+    let mut covariance = [0.0; 9];
+    for p in points {
+        let d = sub(*p, centroid);
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i * 3 + j] += d[i] * d[j];
+            }
+        }
+    }
+    let normal = smallest_eigenvector(&covariance, 3)?;
+    let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+    let normal = [normal[0] / length, normal[1] / length, normal[2] / length];
+    Some(Plane {
+        normal,
+        offset: -dot(normal, centroid),
+    })
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEIGHT: f64 = 0.8;
+
+    // a level floor 0.8 m below the sensor, with every third point an outlier
+    fn floor_with_outliers() -> PointCloud {
+        let (width, height) = (40, 30);
+        let mut seed = 1;
+        let mut random = || (xorshift(&mut seed) % 1000) as f32 / 1000.0;
+        let points = (0..width * height)
+            .map(|i| {
+                let (u, v) = ((i % width) as f32, (i / width) as f32);
+                let point = if i % 3 == 0 {
+                    [
+                        random() * 2.0 - 1.0,
+                        random() * 1.5 - 0.5,
+                        1.0 + random() * 3.0,
+                    ]
+                } else {
+                    [u / 20.0 - 1.0, HEIGHT as f32, 1.0 + v / 10.0]
+                };
+                Some(point)
+            })
+            .collect();
+        PointCloud {
+            width,
+            height,
+            points,
+        }
+    }
+
+    #[test]
+    fn finds_a_plane_among_outliers() {
+        let cloud = floor_with_outliers();
+        let floor = FloorDetector::new().detect(&cloud).unwrap();
+        let [nx, ny, nz] = floor.plane.normal;
+        assert!(nx.abs() < 1e-3 && (ny + 1.0).abs() < 1e-3 && nz.abs() < 1e-3);
+        assert!((floor.height - HEIGHT).abs() < 1e-3, "{}", floor.height);
+        assert!(floor.pitch.abs() < 1e-3 && floor.roll.abs() < 1e-3);
+        assert!(floor.inlier_count >= 800);
+        assert!(floor
+            .inliers
+            .iter()
+            .skip(1)
+            .step_by(3)
+            .all(|&inlier| inlier));
+    }
+
+    #[test]
+    fn respects_gravity() {
+        let cloud = floor_with_outliers();
+        let floor = FloorDetector::new()
+            .with_gravity([0.0, 1.0, 0.1], 10.0)
+            .detect(&cloud)
+            .unwrap();
+        assert!((floor.height - HEIGHT).abs() < 1e-3);
+        let wall = FloorDetector::new()
+            .with_gravity([1.0, 0.0, 0.0], 10.0)
+            .detect(&cloud);
+        assert!(matches!(
+            wall,
+            Err(FreenectError::Algorithm(AlgorithmError::PlaneNotFound))
+        ));
+    }
+
+    #[test]
+    fn needs_three_points() {
+        let cloud = PointCloud {
+            width: 2,
+            height: 1,
+            points: vec![Some([0.0, 1.0, 1.0]), None],
+        };
+        assert!(FloorDetector::new().detect(&cloud).is_err());
+    }
+}
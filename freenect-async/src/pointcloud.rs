@@ -0,0 +1,63 @@
+use crate::{calibration::Intrinsics, filters::DepthImage, FreenectError};
+
+/// Depth camera intrinsics at 640x480, as commonly measured on Xbox 360 Kinects.
+/// Good enough without a calibration of the actual device.
+pub const KINECT_DEPTH_INTRINSICS: Intrinsics = Intrinsics {
+    width: 640,
+    height: 480,
+    fx: 594.21,
+    fy: 591.04,
+    cx: 339.31,
+    cy: 242.74,
+};
+
+/// Points in metres in the depth camera's frame, with x to the right, y down
+/// and z forward. The cloud keeps the image layout, so pixels without a
+/// reading are `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointCloud {
+    pub width: usize,
+    pub height: usize,
+    pub points: Vec<Option<[f32; 3]>>,
+}
+
+impl PointCloud {
+    /// Back-projects a millimetre depth image through the pinhole model.
+    pub fn from_depth(image: &DepthImage, intrinsics: &Intrinsics) -> Result<Self, FreenectError> {
+        if image.width != intrinsics.width as usize || image.height != intrinsics.height as usize {
+            return Err(FreenectError::BadVideoFormat);
+        }
+        let points = image
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, &d)| {
+                (d != 0).then(|| deproject(intrinsics, i % image.width, i / image.width, d))
+            })
+            .collect();
+        Ok(Self {
+            width: image.width,
+            height: image.height,
+            points,
+        })
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<[f32; 3]> {
+        self.points[y * self.width + x]
+    }
+
+    /// The valid points, without their pixel positions.
+    pub fn valid_points(&self) -> impl Iterator<Item = [f32; 3]> + '_ {
+        self.points.iter().flatten().copied()
+    }
+}
+
+/// Camera frame position of a pixel with a depth in millimetres.
+pub(crate) fn deproject(intrinsics: &Intrinsics, u: usize, v: usize, depth_mm: u16) -> [f32; 3] {
+    let z = depth_mm as f32 / 1000.0;
+    [
+        (u as f32 - intrinsics.cx as f32) * z / intrinsics.fx as f32,
+        (v as f32 - intrinsics.cy as f32) * z / intrinsics.fy as f32,
+        z,
+    ]
+}
@@ -0,0 +1,96 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{context::FreenectContext, FreenectError, UsbError};
+
+type Serials = Result<Vec<String>, FreenectError>;
+
+#[derive(Debug, Default)]
+struct ProbeState {
+    result: Option<Serials>,
+    waker: Option<Waker>,
+}
+
+/// Serial numbers of the connected devices, enumerated on a background thread
+/// shared by every probe so polling streams never block on libusb.
+#[derive(Debug)]
+pub(crate) struct SerialProbe {
+    state: Arc<Mutex<ProbeState>>,
+}
+
+impl SerialProbe {
+    pub(crate) fn start() -> Self {
+        let state = Arc::new(Mutex::new(ProbeState::default()));
+        let sent = prober()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(state.clone());
+        if sent.is_err() {
+            // the thread is gone, libfreenect uses -1 for its own failures
+            lock(&state).result = Some(Err(FreenectError::DeviceListError(UsbError::Other(-1))));
+        }
+        Self { state }
+    }
+
+    pub(crate) fn poll(&self, cx: &mut Context<'_>) -> Poll<Serials> {
+        let mut state = lock(&self.state);
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn lock(state: &Mutex<ProbeState>) -> MutexGuard<'_, ProbeState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn prober() -> &'static Mutex<Sender<Arc<Mutex<ProbeState>>>> {
+    static PROBER: OnceLock<Mutex<Sender<Arc<Mutex<ProbeState>>>>> = OnceLock::new();
+    PROBER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("freenect-probe".into())
+            .spawn(move || run(receiver))
+            .expect("failed to spawn the device probe thread");
+        Mutex::new(sender)
+    })
+}
+
+fn run(requests: Receiver<Arc<Mutex<ProbeState>>>) {
+    // a context of its own, as libfreenect contexts can't be shared between threads
+    let mut context = None;
+    while let Ok(first) = requests.recv() {
+        // probes started during the last enumeration share the next one
+        let pending: Vec<_> = std::iter::once(first).chain(requests.try_iter()).collect();
+        if context.is_none() {
+            context = Some(FreenectContext::new());
+        }
+        let serials = match context.as_ref().unwrap() {
+            Ok(context) => context.device_attributes(),
+            Err(e) => Err(e.clone()),
+        };
+        if serials.is_err() {
+            context = None;
+        }
+
+        for state in pending {
+            let waker = {
+                let mut state = lock(&state);
+                state.result = Some(serials.clone());
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    mem::ManuallyDrop,
+    sync::{Arc, Mutex, MutexGuard},
+    task::Poll,
+    time::Duration,
+};
+
+use lending_stream::LendingStream;
+
+use crate::{
+    context::{FreenectContext, FreenectDeviceReady},
+    delay::Delay,
+    device::FreenectDevice,
+    formats::FreenectVideoMode,
+    stats::StreamStats,
+    stream::{DepthStream, OverflowPolicy, VideoStream},
+    video::FreenectVideo,
+    FreenectError,
+};
+
+// shared streams only handle pending events, and look for new ones this often
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+struct ContextInner<M: FreenectDeviceReady> {
+    // serializes every libfreenect call made through the shared handles
+    lock: Mutex<()>,
+    context: FreenectContext<M>,
+}
+
+// libfreenect is only ever entered while holding `lock`
+unsafe impl<M: FreenectDeviceReady> Send for ContextInner<M> {}
+unsafe impl<M: FreenectDeviceReady> Sync for ContextInner<M> {}
+
+impl<M: FreenectDeviceReady> ContextInner<M> {
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Safety: the reference must not outlive the `Arc` holding `self`.
+    unsafe fn context(&self) -> &'static FreenectContext<M> {
+        &*(&self.context as *const FreenectContext<M>)
+    }
+}
+
+/// A `Send + Sync` handle to a [`FreenectContext`], which can be cloned into other
+/// tasks or threads. Calls into libfreenect are serialized by an internal lock,
+/// which streams never hold while waiting for frames.
+pub struct SharedContext<M: FreenectDeviceReady> {
+    inner: Arc<ContextInner<M>>,
+}
+
+impl<M: FreenectDeviceReady> Clone for SharedContext<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M: FreenectDeviceReady> SharedContext<M> {
+    pub fn new(context: FreenectContext<M>) -> Self {
+        Self {
+            inner: Arc::new(ContextInner {
+                lock: Mutex::new(()),
+                context,
+            }),
+        }
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&FreenectContext<M>) -> R) -> R {
+        let _guard = self.inner.lock();
+        f(&self.inner.context)
+    }
+
+    pub fn open_device(&self, index: u32) -> Result<SharedDevice<M>, FreenectError> {
+        let _guard = self.inner.lock();
+        let device = unsafe { self.inner.context() }.open_device(index)?;
+        Ok(SharedDevice::new(self.inner.clone(), device))
+    }
+
+    pub fn open_device_by_serial(&self, serial: &str) -> Result<SharedDevice<M>, FreenectError> {
+        let _guard = self.inner.lock();
+        let device = unsafe { self.inner.context() }.open_device_by_serial(serial)?;
+        Ok(SharedDevice::new(self.inner.clone(), device))
+    }
+}
+
+struct DeviceInner<M: FreenectDeviceReady> {
+    // borrows from `context`, which is dropped after it
+    device: ManuallyDrop<FreenectDevice<'static, M>>,
+    context: Arc<ContextInner<M>>,
+}
+
+unsafe impl<M: FreenectDeviceReady> Send for DeviceInner<M> {}
+unsafe impl<M: FreenectDeviceReady> Sync for DeviceInner<M> {}
+
+impl<M: FreenectDeviceReady> Drop for DeviceInner<M> {
+    fn drop(&mut self) {
+        let _guard = self.context.lock();
+        unsafe { ManuallyDrop::drop(&mut self.device) };
+    }
+}
+
+/// A `Send + Sync` handle to a device opened from a [`SharedContext`]. Motor and
+/// LED commands can be issued through it while another task is streaming.
+pub struct SharedDevice<M: FreenectDeviceReady> {
+    inner: Arc<DeviceInner<M>>,
+}
+
+impl<M: FreenectDeviceReady> Clone for SharedDevice<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M: FreenectDeviceReady> SharedDevice<M> {
+    fn new(context: Arc<ContextInner<M>>, device: FreenectDevice<'static, M>) -> Self {
+        Self {
+            inner: Arc::new(DeviceInner {
+                device: ManuallyDrop::new(device),
+                context,
+            }),
+        }
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&FreenectDevice<'_, M>) -> R) -> R {
+        let _guard = self.inner.context.lock();
+        f(&self.inner.device)
+    }
+
+    /// Safety: the reference must not outlive `self.inner`.
+    unsafe fn device(&self) -> &'static FreenectDevice<'static, M> {
+        &*(&*self.inner.device as *const FreenectDevice<'static, M>)
+    }
+}
+
+impl<M: FreenectVideo> SharedDevice<M> {
+    pub fn start_video_stream(
+        &self,
+        video: &FreenectVideoMode,
+    ) -> Result<SharedVideoStream<M>, FreenectError> {
+        let _guard = self.inner.context.lock();
+        let mut stream = unsafe { self.device() }.start_video_stream(video)?;
+        stream.blocking = false;
+        Ok(SharedVideoStream {
+            stream: ManuallyDrop::new(stream),
+            retry: Delay::new(EVENT_POLL_INTERVAL),
+            device: self.clone(),
+        })
+    }
+
+    pub fn start_depth_stream(
+        &self,
+        depth: &FreenectVideoMode,
+    ) -> Result<SharedDepthStream<M>, FreenectError> {
+        let _guard = self.inner.context.lock();
+        let mut stream = unsafe { self.device() }.start_depth_stream(depth)?;
+        stream.blocking = false;
+        Ok(SharedDepthStream {
+            stream: ManuallyDrop::new(stream),
+            retry: Delay::new(EVENT_POLL_INTERVAL),
+            device: self.clone(),
+        })
+    }
+}
+
+/// A frame lent from the stream's queue. Frames are copied out of libfreenect's
+/// buffers as they arrive, so it stays valid once the lock is released and
+/// another thread processes events.
+#[derive(Debug)]
+pub struct SharedFrame<'c, T> {
+    pub timestamp: u32,
+    pub data: &'c [T],
+}
+
+pub struct SharedVideoStream<M: FreenectVideo> {
+    stream: ManuallyDrop<VideoStream<'static, 'static, M>>,
+    retry: Delay,
+    device: SharedDevice<M>,
+}
+
+unsafe impl<M: FreenectVideo> Send for SharedVideoStream<M> {}
+
+impl<M: FreenectVideo> SharedVideoStream<M> {
+    pub fn device(&self) -> &SharedDevice<M> {
+        &self.device
+    }
+
+    pub fn set_mode(&mut self, video: &FreenectVideoMode) -> Result<(), FreenectError> {
+        let _guard = self.device.inner.context.lock();
+        self.stream.set_mode(video)
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        let _guard = self.device.inner.context.lock();
+        self.stream.stats()
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        let _guard = self.device.inner.context.lock();
+        self.stream.set_overflow_policy(policy);
+    }
+}
+
+impl<M: FreenectVideo> Drop for SharedVideoStream<M> {
+    fn drop(&mut self) {
+        let _guard = self.device.inner.context.lock();
+        unsafe { ManuallyDrop::drop(&mut self.stream) };
+    }
+}
+
+impl<M: FreenectVideo> LendingStream for SharedVideoStream<M> {
+    type Item<'c> = Result<SharedFrame<'c, u8>, FreenectError> where Self: 'c;
+
+    fn poll_next(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item<'_>>> {
+        let guard = self.device.inner.context.lock();
+        let polled = self.stream.poll_next(cx);
+        drop(guard);
+        match polled {
+            Poll::Ready(Some(Ok(frame))) => Poll::Ready(Some(Ok(SharedFrame {
+                timestamp: frame.timestamp,
+                data: frame.data,
+            }))),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                // only event processing wakes the stream, so look for new events shortly
+                self.retry = Delay::new(EVENT_POLL_INTERVAL);
+                let _ = self.retry.poll_elapsed(cx);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct SharedDepthStream<M: FreenectVideo> {
+    stream: ManuallyDrop<DepthStream<'static, 'static, M>>,
+    retry: Delay,
+    device: SharedDevice<M>,
+}
+
+unsafe impl<M: FreenectVideo> Send for SharedDepthStream<M> {}
+
+impl<M: FreenectVideo> SharedDepthStream<M> {
+    pub fn device(&self) -> &SharedDevice<M> {
+        &self.device
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        let _guard = self.device.inner.context.lock();
+        self.stream.stats()
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        let _guard = self.device.inner.context.lock();
+        self.stream.set_overflow_policy(policy);
+    }
+}
+
+impl<M: FreenectVideo> Drop for SharedDepthStream<M> {
+    fn drop(&mut self) {
+        let _guard = self.device.inner.context.lock();
+        unsafe { ManuallyDrop::drop(&mut self.stream) };
+    }
+}
+
+impl<M: FreenectVideo> LendingStream for SharedDepthStream<M> {
+    type Item<'c> = Result<SharedFrame<'c, u16>, FreenectError> where Self: 'c;
+
+    fn poll_next(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item<'_>>> {
+        let guard = self.device.inner.context.lock();
+        let polled = self.stream.poll_next(cx);
+        drop(guard);
+        match polled {
+            Poll::Ready(Some(Ok(frame))) => Poll::Ready(Some(Ok(SharedFrame {
+                timestamp: frame.timestamp,
+                data: frame.data,
+            }))),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                // only event processing wakes the stream, so look for new events shortly
+                self.retry = Delay::new(EVENT_POLL_INTERVAL);
+                let _ = self.retry.poll_elapsed(cx);
+                Poll::Pending
+            }
+        }
+    }
+}
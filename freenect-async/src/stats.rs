@@ -0,0 +1,232 @@
+use std::time::{Duration, Instant};
+
+// weight of the newest sample in the moving averages
+const SMOOTHING: f64 = 0.1;
+// timestamp gaps this many times the usual frame interval count as drops
+const GAP_RATIO: f64 = 1.5;
+
+/// Frame statistics of a stream since it was started, from the device
+/// timestamps and the host times frames arrive and are consumed.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamStats {
+    /// Time since the stream was started.
+    pub elapsed: Duration,
+    /// Frames delivered by libfreenect.
+    pub frames_received: u64,
+    /// Frames handed out by the stream.
+    pub frames_consumed: u64,
+    /// Frames the device skipped, from gaps in the frame timestamps.
+    pub frames_dropped: u64,
+    /// Frames discarded unread because the stream's
+    /// [`OverflowPolicy`](crate::stream::OverflowPolicy) had no room for them.
+    pub frames_overwritten: u64,
+    /// Rate frames arrive at, averaged over recent frames.
+    pub fps: f64,
+    /// Standard deviation of the time between frames arriving.
+    pub jitter: Duration,
+    /// Time from the callback to the frame being handed out, averaged over
+    /// recent frames.
+    pub latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl StreamStats {
+    /// Frames that never reached the consumer, for whatever reason.
+    pub fn frames_lost(&self) -> u64 {
+        self.frames_dropped + self.frames_overwritten
+    }
+}
+
+impl std::fmt::Display for StreamStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.1} fps, jitter {:.1} ms, latency {:.1} ms (max {:.1} ms), {} received, {} dropped, {} overwritten",
+            self.fps,
+            self.jitter.as_secs_f64() * 1000.0,
+            self.latency.as_secs_f64() * 1000.0,
+            self.max_latency.as_secs_f64() * 1000.0,
+            self.frames_received,
+            self.frames_dropped,
+            self.frames_overwritten,
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct StatsRecorder {
+    started: Instant,
+    stats: StreamStats,
+    last_timestamp: Option<u32>,
+    frame_interval: Option<f64>,
+    last_arrival: Option<Instant>,
+    arrival_mean: f64,
+    arrival_variance: f64,
+    latency_mean: f64,
+}
+
+impl Default for StatsRecorder {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            stats: StreamStats::default(),
+            last_timestamp: None,
+            frame_interval: None,
+            last_arrival: None,
+            arrival_mean: 0.0,
+            arrival_variance: 0.0,
+            latency_mean: 0.0,
+        }
+    }
+}
+
+impl StatsRecorder {
+    /// Forgets the previous frame, so a pause in streaming isn't counted as drops.
+    pub(crate) fn interrupt(&mut self) {
+        self.last_timestamp = None;
+        self.last_arrival = None;
+    }
+
+    pub(crate) fn overwritten(&mut self, frames: u64) {
+        self.stats.frames_overwritten += frames;
+    }
+
+    pub(crate) fn received(&mut self, timestamp: u32, arrival: Instant) {
+        self.stats.frames_received += 1;
+
+        if let Some(last) = self.last_timestamp.replace(timestamp) {
+            // the device clock rate isn't documented, so learn the usual interval
+            let delta = timestamp.wrapping_sub(last) as f64;
+            match self.frame_interval {
+                // repeated, or jumped backwards e.g. after a reset
+                _ if delta == 0.0 || delta > (u32::MAX / 2) as f64 => {}
+                None => self.frame_interval = Some(delta),
+                Some(interval) if delta > GAP_RATIO * interval => {
+                    self.stats.frames_dropped += (delta / interval).round() as u64 - 1;
+                }
+                // the first interval seen may have been a gap itself
+                Some(interval) if delta * GAP_RATIO < interval => self.frame_interval = Some(delta),
+                Some(interval) => self.frame_interval = Some(average(interval, delta)),
+            }
+        }
+
+        if let Some(last) = self.last_arrival.replace(arrival) {
+            let interval = arrival.duration_since(last).as_secs_f64();
+            if self.arrival_mean == 0.0 {
+                self.arrival_mean = interval;
+            } else {
+                let diff = interval - self.arrival_mean;
+                self.arrival_mean += SMOOTHING * diff;
+                self.arrival_variance =
+                    (1.0 - SMOOTHING) * (self.arrival_variance + SMOOTHING * diff * diff);
+            }
+        }
+    }
+
+    pub(crate) fn consumed(&mut self, arrival: Instant) {
+        let latency = arrival.elapsed();
+        self.stats.frames_consumed += 1;
+        self.stats.max_latency = self.stats.max_latency.max(latency);
+        self.latency_mean = if self.stats.frames_consumed == 1 {
+            latency.as_secs_f64()
+        } else {
+            average(self.latency_mean, latency.as_secs_f64())
+        };
+    }
+
+    pub(crate) fn snapshot(&self) -> StreamStats {
+        StreamStats {
+            elapsed: self.started.elapsed(),
+            fps: if self.arrival_mean > 0.0 {
+                1.0 / self.arrival_mean
+            } else {
+                0.0
+            },
+            jitter: Duration::from_secs_f64(self.arrival_variance.sqrt()),
+            latency: Duration::from_secs_f64(self.latency_mean),
+            ..self.stats
+        }
+    }
+}
+
+fn average(mean: f64, sample: f64) -> f64 {
+    mean + SMOOTHING * (sample - mean)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // delivers frames with the given device timestamps and host arrival intervals in ms
+    fn record(
+        timestamps: impl IntoIterator<Item = u32>,
+        intervals_ms: impl Fn(usize) -> u64,
+    ) -> StatsRecorder {
+        let mut recorder = StatsRecorder::default();
+        let mut arrival = recorder.started;
+        for (i, timestamp) in timestamps.into_iter().enumerate() {
+            arrival += Duration::from_millis(intervals_ms(i));
+            recorder.received(timestamp, arrival);
+        }
+        recorder
+    }
+
+    #[test]
+    fn steady_frames_have_no_jitter() {
+        let stats = record((0..100).map(|i| i * 1000), |_| 33).snapshot();
+        assert_eq!(stats.frames_received, 100);
+        assert_eq!(stats.frames_dropped, 0);
+        assert!((stats.fps - 1000.0 / 33.0).abs() < 1e-6, "{}", stats.fps);
+        assert!(stats.jitter < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn jitter_is_the_arrival_deviation() {
+        let stats = record(
+            (0..500).map(|i| i * 1000),
+            |i| if i % 2 == 0 { 30 } else { 36 },
+        )
+        .snapshot();
+        assert!((stats.fps - 1000.0 / 33.0).abs() < 0.5, "{}", stats.fps);
+        let jitter_ms = stats.jitter.as_secs_f64() * 1000.0;
+        assert!((2.0..4.0).contains(&jitter_ms), "{jitter_ms}");
+    }
+
+    #[test]
+    fn timestamp_gaps_count_as_drops() {
+        let timestamps = [0, 1000, 2000, 5000, 6000, 8000, 9000];
+        let stats = record(timestamps, |_| 33).snapshot();
+        assert_eq!(stats.frames_dropped, 3);
+        assert_eq!(stats.frames_received, 7);
+    }
+
+    #[test]
+    fn wrapping_and_resets_are_not_drops() {
+        let wrapping = (0..10).map(|i| (u32::MAX - 3500).wrapping_add(i * 1000));
+        assert_eq!(record(wrapping, |_| 33).snapshot().frames_dropped, 0);
+        let reset = [50_000, 51_000, 52_000, 100, 1100, 2100];
+        assert_eq!(record(reset, |_| 33).snapshot().frames_dropped, 0);
+    }
+
+    #[test]
+    fn a_gap_first_is_not_the_frame_interval() {
+        let stats = record([0, 3000, 4000, 5000, 6000], |_| 33).snapshot();
+        assert_eq!(stats.frames_dropped, 0);
+        let stats = record([0, 3000, 4000, 5000, 6000, 9000], |_| 33).snapshot();
+        assert_eq!(stats.frames_dropped, 2);
+    }
+
+    #[test]
+    fn interruptions_are_not_drops() {
+        let mut recorder = record([0, 1000, 2000], |_| 33);
+        recorder.interrupt();
+        recorder.overwritten(2);
+        recorder.received(10_000, Instant::now());
+        recorder.received(11_000, Instant::now());
+        let stats = recorder.snapshot();
+        assert_eq!(stats.frames_dropped, 0);
+        assert_eq!(stats.frames_overwritten, 2);
+        assert_eq!(stats.frames_lost(), 2);
+    }
+}
@@ -1,67 +1,392 @@
 use lending_stream::LendingStream;
-use std::task::{Poll, Waker};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
 
 use crate::{
-    context::FreenectReadyVideo, device::FreenectDevice, formats::{FreenectFormat, FreenectVideoMode}, video::FreenectVideo, FreenectError
+    device::{DeviceShared, FreenectDevice}, formats::{FreenectFormat, FreenectVideoMode}, probe::SerialProbe, stats::{StatsRecorder, StreamStats}, video::FreenectVideo, FreenectError
 };
+#[cfg(feature = "tracing")]
+use crate::logging::TARGET;
 
 const BUSY_LOOP_REPLACE_ME: u32 = 20;
+// how long a stream may go without frames before checking if the device is still there
+const DISCONNECT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+// emptied frame buffers kept around for reuse
+const SPARE_BUFFERS: usize = 2;
+
+/// What a stream does with frames arriving before the previous one was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Only the newest frame is kept.
+    #[default]
+    Latest,
+    /// Up to this many frames are queued, dropping the oldest when it is full.
+    Queue(usize),
+    /// Frames are queued and never dropped. Once this many are waiting, no
+    /// stream on the context processes USB events until the queue is drained,
+    /// so a slow consumer holds back every device on the context. Frames the
+    /// device sends in the meantime are lost on the USB side and show up in
+    /// [`StreamStats::frames_dropped`].
+    Block(usize),
+}
+
+/// Counts the [`OverflowPolicy::Block`] queues of a context that are full.
+/// Shared by the context and the frame slots of its devices.
+#[derive(Debug, Default)]
+pub(crate) struct Backpressure {
+    full: AtomicUsize,
+    waiting: Mutex<Vec<Waker>>,
+}
+
+impl Backpressure {
+    /// Registers `waker` to be woken once no queue is full. Returns whether
+    /// one is, in which case events must not be processed.
+    pub(crate) fn wait(&self, waker: &Waker) -> bool {
+        let mut waiting = self.waiting.lock().unwrap_or_else(|e| e.into_inner());
+        if self.full.load(Ordering::SeqCst) == 0 {
+            return false;
+        }
+        if !waiting.iter().any(|w| w.will_wake(waker)) {
+            waiting.push(waker.clone());
+        }
+        true
+    }
+
+    fn fill(&self) {
+        self.full.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn drain(&self) {
+        if self.full.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let waiting =
+                std::mem::take(&mut *self.waiting.lock().unwrap_or_else(|e| e.into_inner()));
+            waiting.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+type QueuedFrame<T> = (Vec<T>, u32, Instant);
+
+/// Where the libfreenect callbacks of a device leave their frames. Lives in the
+/// device's [`DeviceShared`], which is registered as the libfreenect user
+/// pointer for as long as the device is open.
+///
+/// Frames are copied out of libfreenect's buffers as they arrive, since any
+/// stream on the context may process events and have them overwritten while a
+/// frame is still borrowed.
+#[derive(Debug)]
+pub(crate) struct FrameSlot<T> {
+    pub(crate) active: Cell<bool>,
+    pub(crate) len: Cell<usize>,
+    pub(crate) waker: RefCell<Option<Waker>>,
+    pub(crate) stats: RefCell<StatsRecorder>,
+    policy: Cell<OverflowPolicy>,
+    backpressure: Arc<Backpressure>,
+    // whether this slot's blocking queue is counted as full
+    full: Cell<bool>,
+    queue: RefCell<VecDeque<QueuedFrame<T>>>,
+    // the frame last handed out, only replaced by the next `take`
+    current: RefCell<Vec<T>>,
+    spare: RefCell<Vec<Vec<T>>>,
+}
+
+impl<T> Default for FrameSlot<T> {
+    fn default() -> Self {
+        Self::new(Arc::default())
+    }
+}
+
+impl<T> FrameSlot<T> {
+    pub(crate) fn new(backpressure: Arc<Backpressure>) -> Self {
+        Self {
+            active: Cell::new(false),
+            len: Cell::new(0),
+            waker: RefCell::new(None),
+            stats: RefCell::new(StatsRecorder::default()),
+            policy: Cell::new(OverflowPolicy::default()),
+            backpressure,
+            full: Cell::new(false),
+            queue: RefCell::new(VecDeque::new()),
+            current: RefCell::new(Vec::new()),
+            spare: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl<T: Copy> FrameSlot<T> {
+    fn start(&self, mode: &FreenectVideoMode) -> Result<(), FreenectError> {
+        if self.active.replace(true) {
+            return Err(FreenectError::VideoStreamError);
+        }
+        self.len.set(mode.bytes as usize / std::mem::size_of::<T>());
+        self.clear_queue();
+        self.update_backpressure();
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.active.set(false);
+        // frames left unread are lost like any others the policy drops
+        let unread = self.queue.borrow().len() as u64;
+        self.stats.borrow_mut().overwritten(unread);
+        self.clear_queue();
+        self.update_backpressure();
+        self.waker.borrow_mut().take();
+        self.stats.borrow_mut().interrupt();
+    }
+
+    /// Starts the statistics and policy over, for a newly started stream.
+    fn reset(&self) {
+        *self.stats.borrow_mut() = StatsRecorder::default();
+        self.policy.set(OverflowPolicy::default());
+        self.update_backpressure();
+    }
+
+    pub(crate) fn stats(&self) -> StreamStats {
+        self.stats.borrow().snapshot()
+    }
+
+    pub(crate) fn policy(&self) -> OverflowPolicy {
+        self.policy.get()
+    }
+
+    pub(crate) fn set_policy(&self, policy: OverflowPolicy) {
+        self.policy.set(policy);
+        self.trim_queue();
+        self.update_backpressure();
+    }
+
+    fn capacity(&self) -> usize {
+        match self.policy.get() {
+            OverflowPolicy::Latest => 1,
+            OverflowPolicy::Queue(capacity) => capacity.max(1),
+            OverflowPolicy::Block(_) => usize::MAX,
+        }
+    }
+
+    // holds back event processing on the context while a blocking queue is full
+    fn update_backpressure(&self) {
+        let full = self.active.get()
+            && matches!(self.policy.get(), OverflowPolicy::Block(capacity)
+                if self.queue.borrow().len() >= capacity.max(1));
+        if full != self.full.replace(full) {
+            if full {
+                self.backpressure.fill();
+            } else {
+                self.backpressure.drain();
+            }
+        }
+    }
+
+    // drops the oldest frames beyond the policy's capacity
+    fn trim_queue(&self) {
+        let capacity = self.capacity();
+        let mut dropped = 0;
+        let mut queue = self.queue.borrow_mut();
+        while queue.len() > capacity {
+            let (buffer, ..) = queue.pop_front().unwrap();
+            self.recycle(buffer);
+            dropped += 1;
+        }
+        self.stats.borrow_mut().overwritten(dropped);
+    }
+
+    fn clear_queue(&self) {
+        let mut queue = self.queue.borrow_mut();
+        while let Some((buffer, ..)) = queue.pop_front() {
+            self.recycle(buffer);
+        }
+    }
+
+    fn recycle(&self, buffer: Vec<T>) {
+        let mut spare = self.spare.borrow_mut();
+        if spare.len() < SPARE_BUFFERS {
+            spare.push(buffer);
+        }
+    }
+
+    /// Safety: `data` must point to a frame of `len` elements.
+    unsafe fn deliver(&self, data: *const T, timestamp: u32) {
+        if !self.active.get() {
+            return;
+        }
+        let arrival = Instant::now();
+        let mut buffer = self.spare.borrow_mut().pop().unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(std::slice::from_raw_parts(data, self.len.get()));
+        self.queue.borrow_mut().push_back((buffer, timestamp, arrival));
+        self.stats.borrow_mut().received(timestamp, arrival);
+        self.trim_queue();
+        self.update_backpressure();
+        if let Some(w) = self.waker.borrow().as_ref() {
+            w.wake_by_ref();
+        }
+    }
+
+    /// Hands out the oldest queued frame.
+    ///
+    /// Safety: the slice is only valid until the next call, so the caller must
+    /// hold exclusive access to the stream for as long as it is borrowed.
+    unsafe fn take<'c>(&self) -> Option<(&'c [T], u32)> {
+        let (buffer, timestamp, arrival) = self.queue.borrow_mut().pop_front()?;
+        self.stats.borrow_mut().consumed(arrival);
+        self.update_backpressure();
+        self.recycle(self.current.replace(buffer));
+        let current = &*self.current.as_ptr();
+        Some((std::slice::from_raw_parts(current.as_ptr(), current.len()), timestamp))
+    }
+}
 
 #[derive(Debug)]
 pub struct VideoStream<'a, 'b, D: FreenectVideo> {
     // keep this private
-    pub(crate) device: &'b mut FreenectDevice<'a, D>,
+    pub(crate) device: &'b FreenectDevice<'a, D>,
     pub(crate) counter: u32,
-    pub(crate) out: Option<(&'b [u8], u32)>,
-    pub(crate) waker: Option<Waker>,
+    // shared streams poll while holding the context lock, so they must not wait for events
+    pub(crate) blocking: bool,
+    mode: FreenectVideoMode,
+    mode_changed: bool,
+    watch: DisconnectWatch,
+    // set when restarting after a mode change failed
+    stopped: bool,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 impl<'a, 'b, D: FreenectVideo> VideoStream<'a, 'b, D> {
-    pub(crate) fn new(device: &'b mut FreenectDevice<'a, D>, video: &FreenectVideoMode) -> Result<Self, FreenectError> {
+    pub(crate) fn new(device: &'b FreenectDevice<'a, D>, video: &FreenectVideoMode) -> Result<Self, FreenectError> {
         if let FreenectFormat::Depth(_) = video.format {
             return Err(FreenectError::BadVideoFormat);
         }
 
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(target: TARGET, "video_stream", serial = device.serial(), mode = %video);
+
         unsafe {
             let dev = device.inner;
+            // claim the slot first, so a second stream can't change the mode under a running one
+            device.shared.video.start(video)?;
             if freenect_sys::freenect_set_video_mode(dev, video.into()) < 0 {
+                device.shared.video.stop();
                 return Err(FreenectError::BadVideoFormat);
             }
-            freenect_sys::freenect_start_video(dev);
+            device.shared.video.reset();
             freenect_sys::freenect_set_video_callback(dev, Some(video_callback_standalone));
+            let res = freenect_sys::freenect_start_video(dev);
+            if res < 0 {
+                freenect_sys::freenect_set_video_callback(dev, None);
+                device.shared.video.stop();
+                return Err(FreenectError::StreamStartError(res.into()));
+            }
 
             let stream = Self {
-                device: device,
+                device,
                 counter: 0,
-                out: None,
-                waker: None,
+                blocking: true,
+                mode: *video,
+                mode_changed: false,
+                watch: DisconnectWatch::new(),
+                stopped: false,
+                #[cfg(feature = "tracing")]
+                span,
             };
+            #[cfg(feature = "tracing")]
+            tracing::debug!(target: TARGET, parent: &stream.span, "stream started");
 
             Ok(stream)
         }
     }
 
     pub fn dev_ref(&'b self) -> &'b FreenectDevice<'a, D> {
-        &self.device
+        self.device
+    }
+
+    pub fn mode(&self) -> &FreenectVideoMode {
+        &self.mode
+    }
+
+    /// Frame rate, drop and latency statistics since the stream was started.
+    pub fn stats(&self) -> StreamStats {
+        self.device.shared.video.stats()
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.device.shared.video.policy()
+    }
+
+    /// Sets what happens to frames arriving faster than the stream is polled.
+    /// Frames beyond the new policy's capacity are discarded, oldest first.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.device.shared.video.set_policy(policy);
+    }
+
+    /// Switches the stream to another video mode, e.g. from RGB to IR. The first
+    /// frame in the new mode is flagged with [`CameraFrame::mode_changed`].
+    /// An unsupported mode leaves the stream in its previous one, but if the
+    /// video can't be restarted at all the stream ends.
+    pub fn set_mode(&mut self, video: &FreenectVideoMode) -> Result<(), FreenectError> {
+        if let FreenectFormat::Depth(_) = video.format {
+            return Err(FreenectError::BadVideoFormat);
+        }
+
+        let dev = self.device.inner;
+        let slot = &self.device.shared.video;
+        unsafe {
+            freenect_sys::freenect_stop_video(dev);
+            slot.stop();
+
+            let supported = freenect_sys::freenect_set_video_mode(dev, video.into()) >= 0;
+            if supported {
+                self.mode = *video;
+            } else if freenect_sys::freenect_set_video_mode(dev, (&self.mode).into()) < 0 {
+                // neither mode can be set, so there is nothing left to stream
+                self.stopped = true;
+                return Err(FreenectError::BadVideoFormat);
+            }
+            let res = match slot.start(&self.mode) {
+                Ok(()) => freenect_sys::freenect_start_video(dev),
+                Err(e) => {
+                    self.stopped = true;
+                    return Err(e);
+                }
+            };
+            if res < 0 {
+                slot.stop();
+                self.stopped = true;
+                return Err(FreenectError::StreamStartError(res.into()));
+            }
+            if !supported {
+                return Err(FreenectError::BadVideoFormat);
+            }
+        }
+        self.mode_changed = true;
+        self.counter = 0;
+        self.watch.frame_received();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: TARGET, parent: &self.span, mode = %video, "mode changed");
+
+        Ok(())
     }
 }
 
-extern "C" fn video_callback_standalone<'a>(
+extern "C" fn video_callback_standalone(
     dev: *mut freenect_sys::freenect_device,
     data: *mut std::os::raw::c_void,
     timestamp: u32,
 ) {
     unsafe {
-        let data = data as *mut u8;
-        let data = std::slice::from_raw_parts(data, 640 * 480 * 3);
-        let device =
-            freenect_sys::freenect_get_user(dev) as *mut VideoStream<'a, 'a, FreenectReadyVideo>;
-        let device = &mut *device;
-
-        device.out = Some((data, timestamp));
-        device.counter = 0;
-        if let Some(w) = &device.waker {
-            w.wake_by_ref();
+        let shared = freenect_sys::freenect_get_user(dev) as *const DeviceShared;
+        if let Some(shared) = shared.as_ref() {
+            shared.video.deliver(data as *const u8, timestamp);
         }
     }
 }
@@ -73,8 +398,10 @@ impl<'a, 'b, D: FreenectVideo> Drop
         unsafe {
             freenect_sys::freenect_stop_video(self.device.inner);
             freenect_sys::freenect_set_video_callback(self.device.inner, None);
-            freenect_sys::freenect_set_user(self.device.inner, std::ptr::null_mut());
         }
+        self.device.shared.video.stop();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: TARGET, parent: &self.span, "stream stopped");
     }
 }
 
@@ -87,11 +414,50 @@ impl<'a, 'b, D: FreenectVideo> LendingStream
         &mut self,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item<'_>>> {
-        // retrieve frame if available
-        self.waker = None;
-        if let Some((data, timestamp)) = self.out {
-            self.out = None;
+        if self.watch.disconnected || self.stopped {
+            return Poll::Ready(None);
+        }
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+
+        let device = self.device;
+        let slot = &device.shared.video;
+        // retrieve frame if available, events may have been processed by another stream
+        let mut out = unsafe { slot.take() };
+        if out.is_none() {
+            *slot.waker.borrow_mut() = Some(cx.waker().clone());
+            // a full blocking queue on the context has to be drained first
+            if device.context.backpressure.wait(cx.waker()) {
+                return Poll::Pending;
+            }
+
+            let res = if self.blocking {
+                device.context.process_events()
+            } else {
+                device.context.process_pending_events()
+            };
+            if let Err(e) = res {
+                self.counter = 0;
+                // the device may be gone, look for it right away
+                self.watch.check_now();
+                let e = self.watch.poll(device.serial(), cx).unwrap_or(e);
+                return Poll::Ready(Some(Err(e)));
+            }
+            out = unsafe { slot.take() };
+        }
+
+        if let Some((data, timestamp)) = out {
+            slot.waker.borrow_mut().take();
+            self.counter = 0;
+            self.watch.frame_received();
+            #[cfg(feature = "tracing")]
+            tracing::trace!(target: TARGET, timestamp, "frame delivered");
+            let mode_changed = std::mem::take(&mut self.mode_changed);
             let frame = CameraFrame {
+                mode: self.mode,
+                mode_changed,
                 _held: self,
                 timestamp,
                 data
@@ -99,87 +465,152 @@ impl<'a, 'b, D: FreenectVideo> LendingStream
             return Poll::Ready(Some(Ok(frame)))
         }
 
-        unsafe {freenect_sys::freenect_set_user(self.device.inner, std::mem::transmute(&*self))};
-
-        let res = unsafe { freenect_sys::freenect_process_events(self.device.context.inner) };
-        if res < 0 {
-            self.counter = 0;
-            return Poll::Ready(Some(Err(FreenectError::EventProcessingError)));
+        if let Some(e) = self.watch.poll(device.serial(), cx) {
+            return Poll::Ready(Some(Err(e)));
         }
 
         // arbitrary value to not busy-loop
         // TODO: find a way to not busy-loop that is better
         if self.counter <= BUSY_LOOP_REPLACE_ME {
-
             self.counter += 1;
             cx.waker().wake_by_ref();
         }
-        self.waker = Some(cx.waker().clone());
 
-        // don't leave a dangling pointer
-        unsafe {freenect_sys::freenect_set_user(self.device.inner, std::ptr::null_mut())};
-        return Poll::Pending;
+        Poll::Pending
+    }
+}
+
+/// Looks for a stream's device on the probe thread once no frames arrived
+/// for a while, to end the stream when it was unplugged.
+#[derive(Debug)]
+struct DisconnectWatch {
+    next_check: Instant,
+    probe: Option<SerialProbe>,
+    disconnected: bool,
+}
+
+impl DisconnectWatch {
+    fn new() -> Self {
+        Self {
+            next_check: Instant::now() + DISCONNECT_CHECK_INTERVAL,
+            probe: None,
+            disconnected: false,
+        }
+    }
+
+    fn frame_received(&mut self) {
+        self.next_check = Instant::now() + DISCONNECT_CHECK_INTERVAL;
+        self.probe = None;
+    }
+
+    fn check_now(&mut self) {
+        self.next_check = Instant::now();
+    }
+
+    /// Returns [`FreenectError::DeviceDisconnected`] once the device is gone.
+    /// Devices without a serial number can't be looked up and are assumed connected.
+    fn poll(&mut self, serial: Option<&str>, cx: &mut std::task::Context<'_>) -> Option<FreenectError> {
+        let serial = serial?;
+        if self.probe.is_none() && Instant::now() >= self.next_check {
+            self.next_check = Instant::now() + DISCONNECT_CHECK_INTERVAL;
+            self.probe = Some(SerialProbe::start());
+        }
+        let Poll::Ready(serials) = self.probe.as_ref()?.poll(cx) else {
+            return None;
+        };
+        self.probe = None;
+        // enumeration errors don't tell whether the device is there
+        if serials.is_ok_and(|serials| !serials.iter().any(|s| s == serial)) {
+            self.disconnected = true;
+            return Some(FreenectError::DeviceDisconnected);
+        }
+        None
     }
 }
 
 #[derive(Debug)]
 pub struct DepthStream<'a, 'b, D: FreenectVideo> {
     // keep this private
-    device: &'b mut FreenectDevice<'a, D>,
+    device: &'b FreenectDevice<'a, D>,
     pub(crate) counter: u32,
-    pub(crate) out: Option<(&'b [u16], u32)>,
-    pub(crate) waker: Option<Waker>,
+    // shared streams poll while holding the context lock, so they must not wait for events
+    pub(crate) blocking: bool,
     first: bool,
+    watch: DisconnectWatch,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 impl<'a, 'b, D: FreenectVideo> DepthStream<'a, 'b, D> {
-    pub(crate) fn new(device: &'b mut FreenectDevice<'a, D>, video: &FreenectVideoMode) -> Result<Self, FreenectError> {
+    pub(crate) fn new(device: &'b FreenectDevice<'a, D>, video: &FreenectVideoMode) -> Result<Self, FreenectError> {
         if let FreenectFormat::Video(_) = video.format {
             return Err(FreenectError::BadVideoFormat);
         }
 
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(target: TARGET, "depth_stream", serial = device.serial(), mode = %video);
+
         unsafe {
             let dev = device.inner;
+            device.shared.depth.start(video)?;
             if freenect_sys::freenect_set_depth_mode(dev, video.into()) < 0 {
+                device.shared.depth.stop();
                 return Err(FreenectError::BadVideoFormat);
             }
-            freenect_sys::freenect_start_depth(dev);
+            device.shared.depth.reset();
             freenect_sys::freenect_set_depth_callback(dev, Some(depth_callback_standalone));
+            let res = freenect_sys::freenect_start_depth(dev);
+            if res < 0 {
+                freenect_sys::freenect_set_depth_callback(dev, None);
+                device.shared.depth.stop();
+                return Err(FreenectError::StreamStartError(res.into()));
+            }
 
             let stream = Self {
-                device: device,
+                device,
                 counter: 0,
-                out: None,
-                waker: None,
-                first: true
+                blocking: true,
+                first: true,
+                watch: DisconnectWatch::new(),
+                #[cfg(feature = "tracing")]
+                span,
             };
+            #[cfg(feature = "tracing")]
+            tracing::debug!(target: TARGET, parent: &stream.span, "stream started");
 
             Ok(stream)
         }
     }
 
     pub fn dev_ref(&'b self) -> &'b FreenectDevice<'a, D> {
-        &self.device
+        self.device
+    }
+
+    /// Frame rate, drop and latency statistics since the stream was started.
+    pub fn stats(&self) -> StreamStats {
+        self.device.shared.depth.stats()
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.device.shared.depth.policy()
+    }
+
+    /// Sets what happens to frames arriving faster than the stream is polled.
+    /// Frames beyond the new policy's capacity are discarded, oldest first.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.device.shared.depth.set_policy(policy);
     }
 }
 
-extern "C" fn depth_callback_standalone<'a>(
+extern "C" fn depth_callback_standalone(
     dev: *mut freenect_sys::freenect_device,
     data: *mut std::os::raw::c_void,
     timestamp: u32,
 ) {
     unsafe {
-        let data = data as *mut u16;
-        let data = std::slice::from_raw_parts(data, 640 * 480);
-        let device =
-            freenect_sys::freenect_get_user(dev) as *mut DepthStream<'a, 'a, FreenectReadyVideo>;
-        let device = &mut *device;
-
-        device.out = Some((data, timestamp));
-        println!("counter: {}", device.counter);
-        device.counter = 0;
-        if let Some(w) = &device.waker {
-            w.wake_by_ref();
+        let shared = freenect_sys::freenect_get_user(dev) as *const DeviceShared;
+        if let Some(shared) = shared.as_ref() {
+            shared.depth.deliver(data as *const u16, timestamp);
         }
     }
 }
@@ -191,14 +622,16 @@ impl<'a, 'b, D: FreenectVideo> Drop
         unsafe {
             freenect_sys::freenect_stop_depth(self.device.inner);
             freenect_sys::freenect_set_depth_callback(self.device.inner, None);
-            freenect_sys::freenect_set_user(self.device.inner, std::ptr::null_mut());
         }
+        self.device.shared.depth.stop();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: TARGET, parent: &self.span, "stream stopped");
     }
 }
 
 pub struct VideoDepthStream<'a, 'b, D: FreenectVideo> {
     // keep this private
-    device: &'b mut FreenectDevice<'a, D>,
+    device: &'b FreenectDevice<'a, D>,
 }
 
 impl<'a, 'b, D: FreenectVideo> Drop
@@ -210,8 +643,9 @@ impl<'a, 'b, D: FreenectVideo> Drop
             freenect_sys::freenect_stop_depth(self.device.inner);
             freenect_sys::freenect_set_video_callback(self.device.inner, None);
             freenect_sys::freenect_set_depth_callback(self.device.inner, None);
-            freenect_sys::freenect_set_user(self.device.inner, std::ptr::null_mut());
         }
+        self.device.shared.video.stop();
+        self.device.shared.depth.stop();
     }
 }
 
@@ -224,11 +658,47 @@ impl<'a, 'b, D: FreenectVideo> LendingStream
         &mut self,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Option<Self::Item<'_>>> {
-        // retrieve frame if available
-        self.waker = None;
-        if let Some((data, timestamp)) = self.out {
-            self.out = None;
+        if self.watch.disconnected {
+            return Poll::Ready(None);
+        }
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        #[cfg(feature = "tracing")]
+        let _enter = span.enter();
+
+        let device = self.device;
+        let slot = &device.shared.depth;
+        // retrieve frame if available, events may have been processed by another stream
+        let mut out = unsafe { slot.take() };
+        if out.is_none() {
+            *slot.waker.borrow_mut() = Some(cx.waker().clone());
+            // a full blocking queue on the context has to be drained first
+            if device.context.backpressure.wait(cx.waker()) {
+                return Poll::Pending;
+            }
+
+            let res = if self.blocking {
+                device.context.process_events()
+            } else {
+                device.context.process_pending_events()
+            };
+            if let Err(e) = res {
+                self.counter = 0;
+                // the device may be gone, look for it right away
+                self.watch.check_now();
+                let e = self.watch.poll(device.serial(), cx).unwrap_or(e);
+                return Poll::Ready(Some(Err(e)));
+            }
+            out = unsafe { slot.take() };
+        }
+
+        if let Some((data, timestamp)) = out {
+            slot.waker.borrow_mut().take();
+            self.counter = 0;
             self.first = false;
+            self.watch.frame_received();
+            #[cfg(feature = "tracing")]
+            tracing::trace!(target: TARGET, timestamp, "frame delivered");
             let frame = DepthFrame {
                 _held: self,
                 timestamp,
@@ -237,26 +707,18 @@ impl<'a, 'b, D: FreenectVideo> LendingStream
             return Poll::Ready(Some(Ok(frame)))
         }
 
-        unsafe {freenect_sys::freenect_set_user(self.device.inner, std::mem::transmute(&*self))};
-
-        let res = unsafe { freenect_sys::freenect_process_events(self.device.context.inner) };
-        if res < 0 {
-            self.counter = 0;
-            return Poll::Ready(Some(Err(FreenectError::EventProcessingError)));
+        if let Some(e) = self.watch.poll(device.serial(), cx) {
+            return Poll::Ready(Some(Err(e)));
         }
 
         // arbitrary value to not busy-loop
         // TODO: find a way to not busy-loop that is better
         if self.counter <= BUSY_LOOP_REPLACE_ME {
-
             self.counter += 1;
             cx.waker().wake_by_ref();
         }
-        self.waker = Some(cx.waker().clone());
 
-        // don't leave a dangling pointer
-        unsafe {freenect_sys::freenect_set_user(self.device.inner, std::ptr::null_mut())};
-        return Poll::Pending;
+        Poll::Pending
     }
 }
 
@@ -272,4 +734,123 @@ pub struct CameraFrame<'a, 'b, 'c, D: FreenectVideo> {
     _held: &'c VideoStream<'a, 'b, D>,
     pub timestamp: u32,
     pub data: &'c [u8],
+    pub mode: FreenectVideoMode,
+    /// Set on the first frame after [`VideoStream::set_mode`].
+    pub mode_changed: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::{FreenectDepthFormat, FreenectResolution};
+
+    fn slot(len: usize) -> FrameSlot<u16> {
+        let slot = FrameSlot::default();
+        let mode = FreenectVideoMode {
+            _reserved: 0,
+            format: FreenectFormat::Depth(FreenectDepthFormat::DepthMillimeters),
+            resolution: FreenectResolution::Medium,
+            bytes: (len * 2) as u32,
+            width: len as u16,
+            height: 1,
+            data_bits_per_pixel: 16,
+            padding_bits_per_pixel: 0,
+            framerate: 30,
+            is_valid: true,
+        };
+        slot.start(&mode).unwrap();
+        slot
+    }
+
+    #[test]
+    fn frames_are_copied_on_delivery() {
+        let slot = slot(4);
+        let mut buffer = [1, 2, 3, 4];
+        unsafe {
+            slot.deliver(buffer.as_ptr(), 1);
+            let (frame, timestamp) = slot.take().unwrap();
+            // libfreenect reusing its buffer must not show through a taken frame
+            buffer.copy_from_slice(&[5, 6, 7, 8]);
+            slot.deliver(buffer.as_ptr(), 2);
+            assert_eq!((frame, timestamp), (&[1, 2, 3, 4][..], 1));
+            assert_eq!(slot.take().unwrap(), (&[5, 6, 7, 8][..], 2));
+            assert!(slot.take().is_none());
+        }
+    }
+
+    #[test]
+    fn latest_keeps_only_the_newest_frame() {
+        let slot = slot(1);
+        unsafe {
+            for timestamp in 0..3 {
+                slot.deliver([timestamp as u16].as_ptr(), timestamp);
+            }
+            assert_eq!(slot.take().unwrap().1, 2);
+            assert!(slot.take().is_none());
+        }
+        assert_eq!(slot.stats().frames_overwritten, 2);
+    }
+
+    #[test]
+    fn queue_drops_the_oldest_frames() {
+        let slot = slot(1);
+        slot.set_policy(OverflowPolicy::Queue(2));
+        unsafe {
+            for timestamp in 0..5 {
+                slot.deliver([0].as_ptr(), timestamp);
+            }
+            assert_eq!(slot.take().unwrap().1, 3);
+            assert_eq!(slot.take().unwrap().1, 4);
+            assert!(slot.take().is_none());
+        }
+        assert_eq!(slot.stats().frames_overwritten, 3);
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl std::task::Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn block_holds_back_events_until_drained() {
+        let slot = slot(1);
+        let backpressure = slot.backpressure.clone();
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        slot.set_policy(OverflowPolicy::Block(2));
+        unsafe {
+            slot.deliver([0].as_ptr(), 0);
+            assert!(!backpressure.wait(&waker));
+            // frames arriving while full are still kept
+            for timestamp in 1..4 {
+                slot.deliver([0].as_ptr(), timestamp);
+            }
+            assert!(backpressure.wait(&waker));
+            for timestamp in 0..2 {
+                assert_eq!(slot.take().unwrap().1, timestamp);
+            }
+            assert!(backpressure.wait(&waker));
+            assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+            assert_eq!(slot.take().unwrap().1, 2);
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(!backpressure.wait(&waker));
+        assert_eq!(slot.stats().frames_overwritten, 0);
+    }
+
+    #[test]
+    fn stopping_releases_the_backpressure() {
+        let slot = slot(1);
+        slot.set_policy(OverflowPolicy::Block(1));
+        unsafe { slot.deliver([0].as_ptr(), 0) };
+        let waker = Waker::from(Arc::new(CountingWaker::default()));
+        assert!(slot.backpressure.wait(&waker));
+        slot.stop();
+        assert!(!slot.backpressure.wait(&waker));
+        assert_eq!(slot.stats().frames_overwritten, 1);
+    }
 }
@@ -0,0 +1,285 @@
+use std::{
+    ops::ControlFlow,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use lending_stream::LendingStream;
+
+use crate::{
+    context::FreenectContext,
+    delay::Delay,
+    device::FreenectDevice,
+    formats::{FreenectFormat, FreenectVideoMode},
+    motors_led::{FreenectLedState, FreenectMotors},
+    probe::SerialProbe,
+    video::FreenectVideo,
+    FreenectError,
+};
+
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Device state that gets applied every time the supervised device is (re)opened.
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceSettings {
+    /// Video stream to run, yielding [`SupervisorEvent::Video`].
+    pub video: Option<FreenectVideoMode>,
+    /// Depth stream to run, yielding [`SupervisorEvent::Depth`].
+    pub depth: Option<FreenectVideoMode>,
+    pub led: Option<FreenectLedState>,
+    pub tilt_degree: Option<f64>,
+    pub ir_brightness: Option<u16>,
+}
+
+impl DeviceSettings {
+    /// Streams `mode`, which is either a video or a depth mode.
+    pub fn new(mode: FreenectVideoMode) -> Self {
+        Self {
+            video: None,
+            depth: None,
+            led: None,
+            tilt_degree: None,
+            ir_brightness: None,
+        }
+        .with_mode(mode)
+    }
+
+    /// Also streams `mode`, replacing the previous mode of the same kind.
+    pub fn with_mode(mut self, mode: FreenectVideoMode) -> Self {
+        match mode.format {
+            FreenectFormat::Video(_) => self.video = Some(mode),
+            FreenectFormat::Depth(_) => self.depth = Some(mode),
+        }
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum SupervisorEvent<'c> {
+    Video {
+        timestamp: u32,
+        data: &'c [u8],
+    },
+    Depth {
+        timestamp: u32,
+        data: &'c [u16],
+    },
+    /// The device was lost and has been reopened, frames were missed for `downtime`.
+    Gap {
+        downtime: Duration,
+        reconnects: u32,
+    },
+}
+
+/// Owns a context and keeps a device, identified by its serial number, streaming
+/// across disconnects.
+#[derive(Debug)]
+pub struct CaptureSupervisor<M: FreenectVideo + FreenectMotors> {
+    context: FreenectContext<M>,
+    serial: String,
+    settings: DeviceSettings,
+    retry_interval: Duration,
+    reconnects: u32,
+}
+
+impl<M: FreenectVideo + FreenectMotors> CaptureSupervisor<M> {
+    pub fn new(context: FreenectContext<M>, serial: &str, settings: DeviceSettings) -> Self {
+        Self {
+            context,
+            serial: serial.to_string(),
+            settings,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            reconnects: 0,
+        }
+    }
+
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects
+    }
+
+    pub fn settings(&self) -> &DeviceSettings {
+        &self.settings
+    }
+
+    /// Changes take effect the next time the device is opened.
+    pub fn settings_mut(&mut self) -> &mut DeviceSettings {
+        &mut self.settings
+    }
+
+    pub fn into_context(self) -> FreenectContext<M> {
+        self.context
+    }
+
+    /// Streams frames into `f` until it returns [`ControlFlow::Break`], reopening
+    /// the device and re-applying the settings whenever it is disconnected.
+    /// Failed attempts are retried every retry interval for as long as the device
+    /// is missing, or it can't be told whether it is.
+    pub async fn run<F>(&mut self, mut f: F) -> Result<(), FreenectError>
+    where
+        F: FnMut(SupervisorEvent<'_>) -> ControlFlow<()>,
+    {
+        let mut lost_at: Option<Instant> = None;
+        let mut retry = false;
+        loop {
+            if std::mem::take(&mut retry) {
+                Delay::new(self.retry_interval).await;
+            }
+
+            let device = match self.context.open_device_by_serial(&self.serial) {
+                Ok(device) => device,
+                Err(FreenectError::DeviceNotFoundBySerial(_))
+                | Err(FreenectError::OpenDeviceBySerialError(..)) => {
+                    lost_at.get_or_insert_with(Instant::now);
+                    retry = true;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let Err(e) = apply_settings(&device, &self.settings) {
+                if self.is_connected().await == Some(true) {
+                    return Err(e);
+                }
+                lost_at.get_or_insert_with(Instant::now);
+                retry = true;
+                continue;
+            }
+
+            if let Some(lost) = lost_at.take() {
+                self.reconnects += 1;
+                let gap = SupervisorEvent::Gap {
+                    downtime: lost.elapsed(),
+                    reconnects: self.reconnects,
+                };
+                if f(gap).is_break() {
+                    return Ok(());
+                }
+            }
+
+            match self.stream(&device, &mut f).await {
+                Ok(ControlFlow::Break(())) => return Ok(()),
+                Ok(ControlFlow::Continue(())) => {}
+                Err(e) => {
+                    if self.is_connected().await == Some(true) {
+                        return Err(e);
+                    }
+                    retry = true;
+                }
+            }
+            lost_at = Some(Instant::now());
+        }
+    }
+
+    /// Runs the configured streams until the device is lost or `f` breaks.
+    async fn stream<F>(
+        &self,
+        device: &FreenectDevice<'_, M>,
+        f: &mut F,
+    ) -> Result<ControlFlow<()>, FreenectError>
+    where
+        F: FnMut(SupervisorEvent<'_>) -> ControlFlow<()>,
+    {
+        let mut video = self
+            .settings
+            .video
+            .map(|mode| device.start_video_stream(&mode))
+            .transpose()?;
+        let mut depth = self
+            .settings
+            .depth
+            .map(|mode| device.start_depth_stream(&mode))
+            .transpose()?;
+        if video.is_none() && depth.is_none() {
+            return Err(FreenectError::BadVideoFormat);
+        }
+
+        std::future::poll_fn(|cx| loop {
+            let mut delivered = false;
+            if let Some(stream) = &mut video {
+                let polled = stream.poll_next(cx).map(|item| {
+                    item.map(|frame| {
+                        frame.map(|frame| SupervisorEvent::Video {
+                            timestamp: frame.timestamp,
+                            data: frame.data,
+                        })
+                    })
+                });
+                match deliver(polled, f) {
+                    Poll::Ready(None) => delivered = true,
+                    Poll::Ready(Some(done)) => return Poll::Ready(done),
+                    Poll::Pending => {}
+                }
+            }
+            if let Some(stream) = &mut depth {
+                let polled = stream.poll_next(cx).map(|item| {
+                    item.map(|frame| {
+                        frame.map(|frame| SupervisorEvent::Depth {
+                            timestamp: frame.timestamp,
+                            data: frame.data,
+                        })
+                    })
+                });
+                match deliver(polled, f) {
+                    Poll::Ready(None) => delivered = true,
+                    Poll::Ready(Some(done)) => return Poll::Ready(done),
+                    Poll::Pending => {}
+                }
+            }
+            if !delivered {
+                return Poll::Pending;
+            }
+        })
+        .await
+    }
+
+    /// Looks for the device on the probe thread, `None` if enumeration failed.
+    async fn is_connected(&self) -> Option<bool> {
+        let probe = SerialProbe::start();
+        let serials = std::future::poll_fn(|cx| probe.poll(cx)).await.ok()?;
+        Some(serials.contains(&self.serial))
+    }
+}
+
+/// Hands a polled frame to `f`. Ready with `None` once it was delivered and the
+/// stream should be polled again, and with the outcome once streaming is over.
+fn deliver<F>(
+    polled: Poll<Option<Result<SupervisorEvent<'_>, FreenectError>>>,
+    f: &mut F,
+) -> Poll<Option<Result<ControlFlow<()>, FreenectError>>>
+where
+    F: FnMut(SupervisorEvent<'_>) -> ControlFlow<()>,
+{
+    Poll::Ready(match polled {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Some(Ok(event))) => f(event).is_break().then_some(Ok(ControlFlow::Break(()))),
+        Poll::Ready(Some(Err(FreenectError::DeviceDisconnected)) | None) => {
+            Some(Ok(ControlFlow::Continue(())))
+        }
+        Poll::Ready(Some(Err(e))) => Some(Err(e)),
+    })
+}
+
+fn apply_settings<M: FreenectVideo + FreenectMotors>(
+    device: &FreenectDevice<'_, M>,
+    settings: &DeviceSettings,
+) -> Result<(), FreenectError> {
+    if let Some(led) = settings.led {
+        device.set_led(led)?;
+    }
+    if let Some(deg) = settings.tilt_degree {
+        device.set_tilt_degree(deg)?;
+    }
+    if let Some(brightness) = settings.ir_brightness {
+        device.set_ir_brightness(brightness)?;
+    }
+    Ok(())
+}
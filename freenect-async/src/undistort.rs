@@ -0,0 +1,394 @@
+use crate::{
+    calibration::{Calibration, Distortion, Intrinsics},
+    formats::{FreenectFormat, FreenectVideoFormat, FreenectVideoMode},
+    FreenectError,
+};
+
+// fixed point iterations used to invert the distortion of a single point
+const UNDISTORT_ITERATIONS: usize = 20;
+const IDENTITY: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Pinhole camera with radial and tangential lens distortion.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraModel {
+    pub intrinsics: Intrinsics,
+    pub distortion: Distortion,
+}
+
+impl From<Calibration> for CameraModel {
+    fn from(value: Calibration) -> Self {
+        Self::new(value.intrinsics, value.distortion)
+    }
+}
+
+impl CameraModel {
+    pub fn new(intrinsics: Intrinsics, distortion: Distortion) -> Self {
+        Self {
+            intrinsics,
+            distortion,
+        }
+    }
+
+    /// Projects a point in camera coordinates to a distorted pixel position.
+    pub fn project(&self, point: [f64; 3]) -> Option<[f64; 2]> {
+        if point[2] <= 0.0 {
+            return None;
+        }
+        let (x, y) = self
+            .distortion
+            .distort(point[0] / point[2], point[1] / point[2]);
+        let k = &self.intrinsics;
+        Some([k.fx * x + k.cx, k.fy * y + k.cy])
+    }
+
+    /// Removes the distortion from a pixel position, giving where it lands in
+    /// the undistorted image.
+    pub fn undistort_point(&self, pixel: [f64; 2]) -> [f64; 2] {
+        let k = &self.intrinsics;
+        let (xd, yd) = ((pixel[0] - k.cx) / k.fx, (pixel[1] - k.cy) / k.fy);
+        let (mut x, mut y) = (xd, yd);
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let (dx, dy) = self.distortion.distort(x, y);
+            x -= dx - xd;
+            y -= dy - yd;
+        }
+        [k.fx * x + k.cx, k.fy * y + k.cy]
+    }
+
+    /// Remap table producing undistorted frames with the same camera matrix.
+    pub fn undistort_map(&self) -> Remap {
+        self.rectify_map(IDENTITY, &self.intrinsics)
+    }
+
+    /// Remap table producing frames as seen by a distortion free camera with
+    /// `output` intrinsics, rotated by `rotation` relative to this one.
+    pub fn rectify_map(&self, rotation: [[f64; 3]; 3], output: &Intrinsics) -> Remap {
+        let (width, height) = (output.width as usize, output.height as usize);
+        let mut map = Vec::with_capacity(width * height);
+        for v in 0..height {
+            for u in 0..width {
+                let ray = [
+                    (u as f64 - output.cx) / output.fx,
+                    (v as f64 - output.cy) / output.fy,
+                    1.0,
+                ];
+                // the rotation maps camera rays to rectified ones, so apply its transpose
+                let point: [f64; 3] =
+                    std::array::from_fn(|i| (0..3).map(|j| rotation[j][i] * ray[j]).sum());
+                let source = self
+                    .project(point)
+                    .filter(|p| self.contains(p[0], p[1]))
+                    .map(|p| [p[0] as f32, p[1] as f32]);
+                map.push(source);
+            }
+        }
+
+        Remap {
+            source_width: self.intrinsics.width,
+            source_height: self.intrinsics.height,
+            width: output.width,
+            height: output.height,
+            map,
+        }
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        x >= 0.0
+            && y >= 0.0
+            && x <= self.intrinsics.width as f64 - 1.0
+            && y <= self.intrinsics.height as f64 - 1.0
+    }
+}
+
+/// Precomputed source position of every output pixel, so frames can be
+/// undistorted without solving the lens model again.
+#[derive(Debug, Clone)]
+pub struct Remap {
+    source_width: u16,
+    source_height: u16,
+    width: u16,
+    height: u16,
+    map: Vec<Option<[f32; 2]>>,
+}
+
+impl Remap {
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Bilinear resampling of an 8 bit image with interleaved channels. Pixels
+    /// falling outside the source frame are black. Fails if `src` is shorter
+    /// than a source frame.
+    pub fn apply(&self, src: &[u8], channels: usize) -> Result<Vec<u8>, FreenectError> {
+        if src.len() < self.source_len() * channels {
+            return Err(FreenectError::BadVideoFormat);
+        }
+        Ok(self.bilinear(src, channels, 0, |v| v.round() as u8))
+    }
+
+    /// Undistorts an RGB or IR frame from a [`VideoStream`](crate::stream::VideoStream).
+    pub fn apply_video(
+        &self,
+        mode: &FreenectVideoMode,
+        data: &[u8],
+    ) -> Result<Vec<u8>, FreenectError> {
+        self.check_mode(mode)?;
+        match mode.format {
+            FreenectFormat::Video(FreenectVideoFormat::Rgb) => self.apply(data, 3),
+            FreenectFormat::Video(FreenectVideoFormat::Ir8Bit) => self.apply(data, 1),
+            FreenectFormat::Video(FreenectVideoFormat::Ir10Bit) => {
+                if data.len() < self.source_len() * 2 {
+                    return Err(FreenectError::BadVideoFormat);
+                }
+                let src: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|p| u16::from_ne_bytes([p[0], p[1]]))
+                    .collect();
+                let out = self.bilinear(&src, 1, 0, |v| v.round() as u16);
+                Ok(out.into_iter().flat_map(u16::to_ne_bytes).collect())
+            }
+            _ => Err(FreenectError::BadVideoFormat),
+        }
+    }
+
+    /// Undistorts a frame from a [`DepthStream`](crate::stream::DepthStream).
+    ///
+    /// Depth is sampled from the nearest pixel instead of interpolated, which
+    /// would invent depths between the foreground and background at object edges.
+    pub fn apply_depth(
+        &self,
+        mode: &FreenectVideoMode,
+        data: &[u16],
+    ) -> Result<Vec<u16>, FreenectError> {
+        self.check_mode(mode)?;
+        let invalid = match mode.format {
+            FreenectFormat::Depth(format) => format.invalid_depth(),
+            FreenectFormat::Video(_) => return Err(FreenectError::BadVideoFormat),
+        };
+        if data.len() < self.source_len() {
+            return Err(FreenectError::BadVideoFormat);
+        }
+        let width = self.source_width as usize;
+        Ok(self
+            .map
+            .iter()
+            .map(|source| match source {
+                Some([x, y]) => data[y.round() as usize * width + x.round() as usize],
+                None => invalid,
+            })
+            .collect())
+    }
+
+    fn source_len(&self) -> usize {
+        self.source_width as usize * self.source_height as usize
+    }
+
+    fn check_mode(&self, mode: &FreenectVideoMode) -> Result<(), FreenectError> {
+        if mode.width != self.source_width || mode.height != self.source_height {
+            return Err(FreenectError::BadVideoFormat);
+        }
+        Ok(())
+    }
+
+    // `src` must hold a whole source frame
+    fn bilinear<T: Copy + Into<f32>>(
+        &self,
+        src: &[T],
+        channels: usize,
+        fill: T,
+        convert: impl Fn(f32) -> T,
+    ) -> Vec<T> {
+        let width = self.source_width as usize;
+        let height = self.source_height as usize;
+        let mut out = vec![fill; self.map.len() * channels];
+        for (pixel, source) in out.chunks_exact_mut(channels).zip(&self.map) {
+            let Some([x, y]) = *source else {
+                continue;
+            };
+            let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+            for (c, value) in pixel.iter_mut().enumerate() {
+                let at = |x: usize, y: usize| src[(y * width + x) * channels + c].into();
+                let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+                let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+                *value = convert(top * (1.0 - fy) + bottom * fy);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::{FreenectDepthFormat, FreenectResolution};
+
+    const WIDTH: u16 = 64;
+    const HEIGHT: u16 = 48;
+
+    fn model() -> CameraModel {
+        CameraModel::new(
+            Intrinsics {
+                width: WIDTH,
+                height: HEIGHT,
+                fx: 60.0,
+                fy: 58.0,
+                cx: 31.5,
+                cy: 23.8,
+            },
+            Distortion {
+                k1: 0.2,
+                k2: 0.05,
+                p1: 0.002,
+                p2: -0.001,
+                k3: 0.0,
+            },
+        )
+    }
+
+    fn mode(format: FreenectFormat) -> FreenectVideoMode {
+        FreenectVideoMode {
+            _reserved: 0,
+            format,
+            resolution: FreenectResolution::Low,
+            bytes: 0,
+            width: WIDTH,
+            height: HEIGHT,
+            data_bits_per_pixel: 0,
+            padding_bits_per_pixel: 0,
+            framerate: 30,
+            is_valid: true,
+        }
+    }
+
+    // a gradient, which bilinear sampling reproduces exactly
+    fn gradient(x: f32, y: f32) -> f32 {
+        x + 2.0 * y
+    }
+
+    #[test]
+    fn undistort_point_inverts_project() {
+        let model = model();
+        let ideal = CameraModel::new(model.intrinsics, Distortion::default());
+        for point in [
+            [0.0, 0.0, 1.0],
+            [0.2, -0.1, 1.0],
+            [-0.3, 0.25, 1.2],
+            [0.4, 0.3, 1.1],
+        ] {
+            let distorted = model.project(point).unwrap();
+            let undistorted = model.undistort_point(distorted);
+            let expected = ideal.project(point).unwrap();
+            assert!(
+                (undistorted[0] - expected[0]).abs() < 1e-6,
+                "{undistorted:?}"
+            );
+            assert!(
+                (undistorted[1] - expected[1]).abs() < 1e-6,
+                "{undistorted:?}"
+            );
+        }
+        assert!(model.project([0.0, 0.0, -1.0]).is_none());
+    }
+
+    #[test]
+    fn samples_video_through_the_map() {
+        let map = model().undistort_map();
+        let (width, height) = (WIDTH as usize, HEIGHT as usize);
+        let ir: Vec<u16> = (0..width * height)
+            .map(|i| gradient((i % width) as f32, (i / width) as f32) as u16)
+            .collect();
+        let data: Vec<u8> = ir.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        let out = map
+            .apply_video(
+                &mode(FreenectFormat::Video(FreenectVideoFormat::Ir10Bit)),
+                &data,
+            )
+            .unwrap();
+        let out: Vec<u16> = out
+            .chunks_exact(2)
+            .map(|p| u16::from_ne_bytes([p[0], p[1]]))
+            .collect();
+
+        let mut mapped = 0;
+        for (value, source) in out.iter().zip(&map.map) {
+            match source {
+                Some([x, y]) => {
+                    assert!((*value as f32 - gradient(*x, *y)).abs() <= 0.5);
+                    mapped += 1;
+                }
+                None => assert_eq!(*value, 0),
+            }
+        }
+        // the corners fall outside the pincushion distorted frame
+        assert!(mapped > width * height * 3 / 4 && mapped < width * height);
+
+        let rgb: Vec<u8> = (0..width * height * 3)
+            .map(|i| (i % 3 * 40) as u8 + (i / 3 % width) as u8)
+            .collect();
+        let out = map
+            .apply_video(&mode(FreenectFormat::Video(FreenectVideoFormat::Rgb)), &rgb)
+            .unwrap();
+        for (pixel, source) in out.chunks_exact(3).zip(&map.map) {
+            if let Some([x, _]) = source {
+                for (c, value) in pixel.iter().enumerate() {
+                    assert!((*value as f32 - (c * 40) as f32 - x).abs() <= 0.5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_the_nearest_depth() {
+        let map = model().undistort_map();
+        let width = WIDTH as usize;
+        let depth: Vec<u16> = (0..width * HEIGHT as usize)
+            .map(|i| 1000 + gradient((i % width) as f32, (i / width) as f32) as u16)
+            .collect();
+        let format = FreenectDepthFormat::DepthMillimeters;
+        let out = map
+            .apply_depth(&mode(FreenectFormat::Depth(format)), &depth)
+            .unwrap();
+        for (value, source) in out.iter().zip(&map.map) {
+            let expected = match source {
+                Some([x, y]) => 1000 + gradient(x.round(), y.round()) as u16,
+                None => format.invalid_depth(),
+            };
+            assert_eq!(*value, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_short_frames() {
+        let map = model().undistort_map();
+        let pixels = WIDTH as usize * HEIGHT as usize;
+        for (format, len) in [
+            (FreenectVideoFormat::Rgb, pixels * 3),
+            (FreenectVideoFormat::Ir8Bit, pixels),
+            (FreenectVideoFormat::Ir10Bit, pixels * 2),
+        ] {
+            let mode = mode(FreenectFormat::Video(format));
+            assert!(map.apply_video(&mode, &vec![0; len]).is_ok());
+            assert!(matches!(
+                map.apply_video(&mode, &vec![0; len - 1]),
+                Err(FreenectError::BadVideoFormat)
+            ));
+        }
+        let mode = mode(FreenectFormat::Depth(FreenectDepthFormat::DepthMillimeters));
+        assert!(map.apply_depth(&mode, &vec![0; pixels - 1]).is_err());
+    }
+
+    #[test]
+    fn zero_sized_intrinsics_map_nothing() {
+        let mut model = model();
+        model.intrinsics.width = 0;
+        model.intrinsics.height = 0;
+        assert!(!model.contains(0.0, 0.0));
+    }
+}
@@ -1,51 +1,216 @@
 use std::{fmt, mem::{transmute, MaybeUninit}};
 
+use bitflags::bitflags;
+
 use crate::{
+    alternating::AlternatingStream,
     context::{
-        FreenectDeviceMode, FreenectDeviceReady, FreenectReadyAll, FreenectReadyVideo,
-        FreenectReadyVideoMotors,
+        FreenectDeviceMode, FreenectDeviceReady, FreenectReadyAll, FreenectReadyDynamic,
+        FreenectReadyVideo, FreenectReadyVideoMotors, Subdevices,
     }, device::FreenectDevice, formats::{FreenectDepthFormat, FreenectFormat, FreenectResolution, FreenectVideoFormat, FreenectVideoMode}, stream::{DepthStream, VideoDepthStream, VideoStream}, FreenectError
 };
+#[cfg(target_os = "linux")]
+use crate::device::KinectModel;
 
 const MAX_IR_BRIGHTNESS: u16 = 50;
-const MIN_IR_BRIGHTNESS: u16 = 50;
+const MIN_IR_BRIGHTNESS: u16 = 1;
+// brightness change per step of the automatic adjustment
+const AUTO_BRIGHTNESS_STEP: u16 = 2;
+// depth frames averaged before each adjustment, after skipping the settling ones
+const AUTO_BRIGHTNESS_FRAMES: u32 = 5;
+const AUTO_BRIGHTNESS_SETTLE_FRAMES: u32 = 2;
+// hole fraction under which the brightness is left alone
+const AUTO_BRIGHTNESS_TOLERANCE: f64 = 0.005;
 
 pub trait FreenectVideo: FreenectDeviceReady {}
 
+bitflags! {
+    /// Camera features toggled with `freenect_set_flag`. The first four are
+    /// written to the RGB sensor's registers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct CameraFlags: u32 {
+        const AUTO_EXPOSURE = freenect_sys::freenect_flag_FREENECT_AUTO_EXPOSURE;
+        const AUTO_FLICKER = freenect_sys::freenect_flag_FREENECT_AUTO_FLICKER;
+        const AUTO_WHITE_BALANCE = freenect_sys::freenect_flag_FREENECT_AUTO_WHITE_BALANCE;
+        const RAW_COLOR = freenect_sys::freenect_flag_FREENECT_RAW_COLOR;
+        const MIRROR_DEPTH = freenect_sys::freenect_flag_FREENECT_MIRROR_DEPTH;
+        const MIRROR_VIDEO = freenect_sys::freenect_flag_FREENECT_MIRROR_VIDEO;
+        /// Only supported by Kinect for Windows units.
+        const NEAR_MODE = freenect_sys::freenect_flag_FREENECT_NEAR_MODE;
+    }
+}
+
+/// Range the depth camera measures in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DepthRange {
+    /// About 80cm to 4m, with readings out to about 8m at lower precision.
+    #[default]
+    Default,
+    /// About 40cm to 3m, only on Kinect for Windows units.
+    Near,
+}
+
 impl FreenectVideo for FreenectReadyVideo {}
 
 impl FreenectVideo for FreenectReadyVideoMotors {}
 
 impl FreenectVideo for FreenectReadyAll {}
 
+impl FreenectVideo for FreenectReadyDynamic {}
+
 impl<'a, D> FreenectDevice<'a, D>
 where
     D: FreenectVideo,
 {
     pub fn get_ir_brightness(&self) -> Result<u16, FreenectError> {
+        self.require(Subdevices::CAMERA)?;
         unsafe {
             let res = freenect_sys::freenect_get_ir_brightness(self.inner);
             if res < 0 {
-                return Err(FreenectError::GetBrightnessError);
+                return Err(FreenectError::GetBrightnessError(res.into()));
             }
 
             Ok(res as u16)
         }
     }
 
-    pub fn set_ir_brightness(&mut self, brightness: u16) -> Result<(), FreenectError> {
-        if brightness > MAX_IR_BRIGHTNESS || brightness < MIN_IR_BRIGHTNESS {
+    pub fn set_ir_brightness(&self, brightness: u16) -> Result<(), FreenectError> {
+        if !(MIN_IR_BRIGHTNESS..=MAX_IR_BRIGHTNESS).contains(&brightness) {
             return Err(FreenectError::BrightnessOutOfRange(brightness));
         }
+        self.require(Subdevices::CAMERA)?;
         unsafe {
-            if freenect_sys::freenect_set_ir_brightness(self.inner, brightness) < 0 {
-                return Err(FreenectError::SetBrightnessError);
+            let res = freenect_sys::freenect_set_ir_brightness(self.inner, brightness);
+            if res < 0 {
+                return Err(FreenectError::SetBrightnessError(res.into()));
             }
         }
 
         Ok(())
     }
 
+    /// Enables or disables each of `flags`.
+    pub fn set_camera_flags(&self, flags: CameraFlags, enabled: bool) -> Result<(), FreenectError> {
+        self.require(Subdevices::CAMERA)?;
+        let value = if enabled {
+            freenect_sys::freenect_flag_value_FREENECT_ON
+        } else {
+            freenect_sys::freenect_flag_value_FREENECT_OFF
+        };
+        for flag in flags.iter() {
+            let res = unsafe { freenect_sys::freenect_set_flag(self.inner, flag.bits(), value) };
+            if res < 0 {
+                return Err(FreenectError::SetFlagError(flag, res.into()));
+            }
+            let shared = &self.shared;
+            shared.known_flags.set(shared.known_flags.get() | flag);
+            shared.flags.set(shared.flags.get().difference(flag));
+            if enabled {
+                shared.flags.set(shared.flags.get() | flag);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The value `flag` was last set to through this device, not read from the
+    /// hardware: libfreenect has no call to read flags back. `None` until the
+    /// flag is set, and stale if another process changes it.
+    pub fn cached_camera_flag(&self, flag: CameraFlags) -> Option<bool> {
+        if !self.shared.known_flags.get().contains(flag) {
+            return None;
+        }
+        Some(self.shared.flags.get().contains(flag))
+    }
+
+    /// Exposure time of the RGB camera, in microseconds.
+    pub fn get_exposure(&self) -> Result<u32, FreenectError> {
+        self.require(Subdevices::CAMERA)?;
+        let mut time_us = 0;
+        let res = unsafe { freenect_sys::freenect_get_exposure(self.inner, &mut time_us) };
+        if res < 0 {
+            return Err(FreenectError::GetExposureError(res.into()));
+        }
+        Ok(time_us as u32)
+    }
+
+    /// Only has an effect once [`CameraFlags::AUTO_EXPOSURE`] is disabled.
+    pub fn set_exposure(&self, time_us: u32) -> Result<(), FreenectError> {
+        self.require(Subdevices::CAMERA)?;
+        let res = unsafe { freenect_sys::freenect_set_exposure(self.inner, time_us as i32) };
+        if res < 0 {
+            return Err(FreenectError::SetExposureError(res.into()));
+        }
+        Ok(())
+    }
+
+    /// Turns off every automatic adjustment of the RGB camera and fixes its
+    /// exposure, for captures with repeatable photometry.
+    pub fn lock_exposure(&self, time_us: u32) -> Result<(), FreenectError> {
+        self.set_camera_flags(
+            CameraFlags::AUTO_EXPOSURE | CameraFlags::AUTO_FLICKER | CameraFlags::AUTO_WHITE_BALANCE,
+            false,
+        )?;
+        self.set_exposure(time_us)
+    }
+
+    /// Switching the IR projector off, e.g. for captures of the ambient IR, fails
+    /// with [`FreenectError::Unsupported`]: libfreenect has no call for it and
+    /// the projector stays on while the camera streams. It can only be dimmed
+    /// down to an IR brightness of 1.
+    pub fn set_projector(&self, enabled: bool) -> Result<(), FreenectError> {
+        self.require(Subdevices::CAMERA)?;
+        if !enabled {
+            return Err(FreenectError::Unsupported("switching the IR projector off"));
+        }
+        Ok(())
+    }
+
+    /// Feeds a depth frame to `auto` and applies the brightness it settles on.
+    /// If that fails, `auto` is left as it was before the frame.
+    pub fn adjust_ir_brightness(
+        &self,
+        auto: &mut AutoIrBrightness,
+        depth: &[u16],
+    ) -> Result<(), FreenectError> {
+        let before = auto.clone();
+        if let Some(brightness) = auto.update(depth) {
+            if let Err(e) = self.set_ir_brightness(brightness) {
+                // the device kept its brightness, so measure it again and retry
+                *auto = before;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with [`FreenectError::Unsupported`] on Xbox 360 units, including
+    /// the model 1473, on which enabling near mode would silently do nothing.
+    #[cfg(target_os = "linux")]
+    pub fn supports_near_mode(&self) -> Result<(), FreenectError> {
+        match self.model()? {
+            KinectModel::KinectForWindows => Ok(()),
+            KinectModel::Xbox360 => Err(FreenectError::Unsupported("near mode")),
+        }
+    }
+
+    /// Near mode shifts the depth range closer to the sensor, to about 40cm to 3m.
+    #[cfg(target_os = "linux")]
+    pub fn set_near_mode(&self, enabled: bool) -> Result<(), FreenectError> {
+        self.supports_near_mode()?;
+        self.set_camera_flags(CameraFlags::NEAR_MODE, enabled)
+    }
+
+    /// Switching back to [`DepthRange::Default`] always succeeds on Xbox 360
+    /// units, which can't leave it.
+    #[cfg(target_os = "linux")]
+    pub fn set_depth_range(&self, range: DepthRange) -> Result<(), FreenectError> {
+        match (range, self.model()?) {
+            (DepthRange::Default, KinectModel::Xbox360) => Ok(()),
+            (range, _) => self.set_near_mode(range == DepthRange::Near),
+        }
+    }
+
     pub fn get_supported_video_modes(&self) -> Vec<FreenectVideoMode> {
         unsafe {
             let count = freenect_sys::freenect_get_video_mode_count() as usize;
@@ -109,24 +274,170 @@ where
     }
 
     pub fn start_video_stream<'b>(
-        &'b mut self,
+        &'b self,
         video: &FreenectVideoMode,
     ) -> Result<VideoStream<'a, 'b, D>, FreenectError> {
-
+        self.require(Subdevices::CAMERA)?;
         VideoStream::new(self, video)
     }
 
+    pub fn start_alternating_stream<'b>(
+        &'b self,
+        rgb: &FreenectVideoMode,
+        ir: &FreenectVideoMode,
+        frames_per_mode: u32,
+    ) -> Result<AlternatingStream<'a, 'b, D>, FreenectError> {
+        AlternatingStream::new(self, rgb, ir, frames_per_mode)
+    }
+
     pub fn start_depth_stream<'b>(
-        &'b mut self,
+        &'b self,
         depth: &FreenectVideoMode,
     ) -> Result<DepthStream<'a, 'b, D>, FreenectError> {
+        self.require(Subdevices::CAMERA)?;
         DepthStream::new(self, depth)
     }
 
     pub fn start_video_depth_stream<'b>(
-        &'b mut self,
+        &'b self,
         depth: &FreenectVideoMode,
     ) -> Result<VideoDepthStream<'a, 'b, D>, FreenectError> {
         todo!()
     }
 }
+
+/// Hill climbing on the IR brightness to minimise the holes in depth frames,
+/// which appear both on dark surfaces and where reflections saturate the sensor.
+#[derive(Debug, Clone)]
+pub struct AutoIrBrightness {
+    invalid: u16,
+    brightness: u16,
+    increasing: bool,
+    settling: u32,
+    frames: u32,
+    holes: f64,
+    previous: Option<f64>,
+}
+
+impl AutoIrBrightness {
+    /// `brightness` is the current one, e.g. from [`FreenectDevice::get_ir_brightness`].
+    pub fn new(format: FreenectDepthFormat, brightness: u16) -> Self {
+        Self {
+            invalid: format.invalid_depth(),
+            brightness: brightness.clamp(MIN_IR_BRIGHTNESS, MAX_IR_BRIGHTNESS),
+            increasing: true,
+            settling: 0,
+            frames: 0,
+            holes: 0.0,
+            previous: None,
+        }
+    }
+
+    pub fn brightness(&self) -> u16 {
+        self.brightness
+    }
+
+    /// Mean fraction of invalid pixels measured at the current brightness.
+    pub fn hole_fraction(&self) -> Option<f64> {
+        self.previous
+    }
+
+    /// Returns the brightness to switch to, once enough frames were seen at the
+    /// current one. It is taken as applied from then on.
+    pub fn update(&mut self, depth: &[u16]) -> Option<u16> {
+        if self.settling > 0 {
+            self.settling -= 1;
+            return None;
+        }
+        if depth.is_empty() {
+            return None;
+        }
+        let invalid = depth.iter().filter(|&&d| d == self.invalid).count();
+        self.holes += invalid as f64 / depth.len() as f64;
+        self.frames += 1;
+        if self.frames < AUTO_BRIGHTNESS_FRAMES {
+            return None;
+        }
+
+        let holes = self.holes / self.frames as f64;
+        self.holes = 0.0;
+        self.frames = 0;
+        let previous = self.previous.replace(holes);
+        if holes < AUTO_BRIGHTNESS_TOLERANCE {
+            return None;
+        }
+        if previous.is_some_and(|previous| holes > previous) {
+            self.increasing = !self.increasing;
+        }
+
+        let mut next = self.step();
+        if next == self.brightness {
+            // hit a bound, head back the other way
+            self.increasing = !self.increasing;
+            next = self.step();
+        }
+        self.brightness = next;
+        self.settling = AUTO_BRIGHTNESS_SETTLE_FRAMES;
+        Some(next)
+    }
+
+    fn step(&self) -> u16 {
+        if self.increasing {
+            (self.brightness + AUTO_BRIGHTNESS_STEP).min(MAX_IR_BRIGHTNESS)
+        } else {
+            self.brightness
+                .saturating_sub(AUTO_BRIGHTNESS_STEP)
+                .max(MIN_IR_BRIGHTNESS)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the controller against a sensor whose hole fraction depends on the brightness
+    fn run(auto: &mut AutoIrBrightness, frames: usize, holes: impl Fn(u16) -> f64) -> Vec<u16> {
+        let mut visited = vec![auto.brightness()];
+        for _ in 0..frames {
+            let invalid = (holes(auto.brightness()).clamp(0.0, 1.0) * 1000.0) as usize;
+            let frame: Vec<u16> = (0..1000)
+                .map(|i| if i < invalid { 0 } else { 1000 })
+                .collect();
+            if let Some(brightness) = auto.update(&frame) {
+                visited.push(brightness);
+            }
+        }
+        visited
+    }
+
+    #[test]
+    fn converges_to_the_fewest_holes() {
+        let mut auto = AutoIrBrightness::new(FreenectDepthFormat::DepthMillimeters, 10);
+        let visited = run(&mut auto, 400, |b| (b as f64 - 30.0).abs() * 0.01 + 0.01);
+        assert!((26..=34).contains(&auto.brightness()), "{visited:?}");
+        let settled = &visited[visited.len() / 2..];
+        assert!(settled.iter().all(|b| (26..=34).contains(b)));
+    }
+
+    #[test]
+    fn stays_within_the_brightness_range() {
+        let mut auto = AutoIrBrightness::new(FreenectDepthFormat::DepthMillimeters, 5);
+        let visited = run(&mut auto, 200, |b| b as f64 * 0.01);
+        assert!(visited.contains(&MIN_IR_BRIGHTNESS), "{visited:?}");
+        assert!(visited.iter().all(|&b| b >= MIN_IR_BRIGHTNESS));
+
+        let auto = AutoIrBrightness::new(FreenectDepthFormat::DepthMillimeters, 0);
+        assert_eq!(auto.brightness(), MIN_IR_BRIGHTNESS);
+        let auto = AutoIrBrightness::new(FreenectDepthFormat::DepthMillimeters, 80);
+        assert_eq!(auto.brightness(), MAX_IR_BRIGHTNESS);
+    }
+
+    #[test]
+    fn keeps_a_brightness_without_holes() {
+        let mut auto = AutoIrBrightness::new(FreenectDepthFormat::DepthMillimeters, 20);
+        let visited = run(&mut auto, 50, |_| 0.0);
+        assert_eq!(visited, [20]);
+        assert_eq!(auto.hole_fraction(), Some(0.0));
+    }
+}